@@ -0,0 +1,423 @@
+//! Recording of sessions to, and reading them back from, asciicast v2 files.
+//!
+//! See <https://github.com/asciinema/asciinema/blob/develop/doc/asciicast-v2.md>.
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::filter::{keep_error, Filter};
+use crate::json;
+use crate::screen::Screen;
+use crate::term::Winsize;
+
+/// A single event of a recorded session.
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Seconds since the start of the session.
+    pub time: f64,
+    /// Type of the event: "o" for output, "i" for input, "r" for resizes.
+    pub code: String,
+    pub data: String,
+}
+
+/// A recorded session, as read from an asciicast v2 file.
+#[derive(Debug, Clone)]
+pub struct Cast {
+    pub width: u16,
+    pub height: u16,
+    pub events: Vec<Event>,
+}
+
+impl Cast {
+    /// Reads the asciicast v2 file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Cast, Box<dyn Error>> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        let header = json::parse(&lines.next().ok_or("empty asciicast file")??)?;
+        if header.get("version").and_then(json::Value::as_f64) != Some(2.0) {
+            return Err("unsupported asciicast version, expected 2".into());
+        }
+        let dimension = |key| match header.get(key).and_then(json::Value::as_f64) {
+            None => Err(format!("asciicast header is missing \"{}\"", key)),
+            Some(n) if n.fract() == 0.0 && (1.0..=f64::from(u16::MAX)).contains(&n) => Ok(n as u16),
+            Some(n) => Err(format!("invalid \"{}\" in asciicast header: {}", key, n)),
+        };
+        let (width, height) = (dimension("width")?, dimension("height")?);
+
+        let mut events = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = json::parse(&line)?;
+            let event = match event.as_array() {
+                Some([time, code, data]) => Event {
+                    time: time.as_f64().ok_or("event time is not a number")?,
+                    code: code
+                        .as_str()
+                        .ok_or("event type is not a string")?
+                        .to_string(),
+                    data: data
+                        .as_str()
+                        .ok_or("event data is not a string")?
+                        .to_string(),
+                },
+                _ => return Err("asciicast event is not a 3 element array".into()),
+            };
+            if event.code == "r" && parse_size(&event.data).is_none() {
+                return Err(format!("invalid size in resize event: {:?}", event.data).into());
+            }
+            events.push(event);
+        }
+
+        Ok(Cast {
+            width,
+            height,
+            events,
+        })
+    }
+
+    /// Plays back the output of the session on an emulated screen, up to
+    /// `time` seconds into it or to the end, and returns the screen.
+    pub fn screen_at(&self, time: Option<f64>) -> Screen {
+        let mut screen = Screen::new(self.height, self.width);
+        for event in &self.events {
+            if time.is_some_and(|time| event.time > time) {
+                break;
+            }
+            match event.code.as_str() {
+                "o" => screen.process(event.data.as_bytes()),
+                "r" => {
+                    if let Some((cols, rows)) = parse_size(&event.data) {
+                        screen.resize(rows, cols);
+                    }
+                }
+                _ => {}
+            }
+        }
+        screen
+    }
+}
+
+/// Writes the events of a session to an asciicast v2 file.
+pub struct Recorder {
+    file: BufWriter<File>,
+    start: Instant,
+    record_input: bool,
+    // Trailing bytes of an incomplete UTF-8 sequence, per event type.
+    partial_output: Vec<u8>,
+    partial_input: Vec<u8>,
+    // The first error writing the file as a filter, for `take_error`.
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    /// Creates the file at `path` and writes the asciicast header for a
+    /// terminal of size `winsize` running `cmd`.
+    /// Input is only recorded if `record_input` is set.
+    pub fn create(
+        path: impl AsRef<Path>,
+        winsize: &Winsize,
+        cmd: &[String],
+        record_input: bool,
+    ) -> Result<Recorder, Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        write!(
+            file,
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}",
+            winsize.ws_col, winsize.ws_row, timestamp
+        )?;
+        if !cmd.is_empty() {
+            write!(file, ", \"command\": \"{}\"", escape(&cmd.join(" ")))?;
+        }
+        write!(file, ", \"env\": {{")?;
+        let vars: Vec<_> = ["SHELL", "TERM"]
+            .iter()
+            .filter_map(|var| env::var(var).ok().map(|val| (var, val)))
+            .collect();
+        for (i, (var, val)) in vars.iter().enumerate() {
+            let sep = if i == 0 { "" } else { ", " };
+            write!(file, "{}\"{}\": \"{}\"", sep, var, escape(val))?;
+        }
+        writeln!(file, "}}}}")?;
+        file.flush()?;
+
+        Ok(Recorder {
+            file,
+            start: Instant::now(),
+            record_input,
+            partial_output: Vec::new(),
+            partial_input: Vec::new(),
+            error: None,
+        })
+    }
+
+    /// Records `data` written by the program to the terminal.
+    pub fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        let text = take_utf8(&mut self.partial_output, data);
+        self.event("o", &text)
+    }
+
+    /// Records `data` typed into the terminal, if input is being recorded.
+    pub fn input(&mut self, data: &[u8]) -> io::Result<()> {
+        if !self.record_input {
+            return Ok(());
+        }
+        let text = take_utf8(&mut self.partial_input, data);
+        self.event("i", &text)
+    }
+
+    /// Records a change of the terminal size.
+    pub fn resize(&mut self, winsize: &Winsize) -> io::Result<()> {
+        self.event("r", &format!("{}x{}", winsize.ws_col, winsize.ws_row))
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let time = self.start.elapsed().as_secs_f64();
+        writeln!(
+            self.file,
+            "[{:.6}, \"{}\", \"{}\"]",
+            time,
+            code,
+            escape(data)
+        )?;
+        self.file.flush()
+    }
+}
+
+/// Records the session as it passes through, without changing anything.
+impl Filter for Recorder {
+    fn on_input(&mut self, data: &[u8]) -> Vec<u8> {
+        let res = self.input(data);
+        keep_error(&mut self.error, res);
+        data.to_vec()
+    }
+
+    fn on_output(&mut self, data: &[u8]) -> Vec<u8> {
+        let res = self.output(data);
+        keep_error(&mut self.error, res);
+        data.to_vec()
+    }
+
+    fn on_resize(&mut self, winsize: &Winsize) {
+        let res = self.resize(winsize);
+        keep_error(&mut self.error, res);
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+/// Appends `data` to the `partial` bytes left over from the last call and
+/// returns the longest prefix that can be decoded, keeping an incomplete
+/// trailing UTF-8 sequence back for the next call.
+/// Invalid sequences are replaced with U+FFFD.
+fn take_utf8(partial: &mut Vec<u8>, data: &[u8]) -> String {
+    partial.extend_from_slice(data);
+    let valid = match str::from_utf8(partial) {
+        Ok(_) => partial.len(),
+        // Only hold back bytes at the very end that may still be completed.
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        Err(_) => partial.len(),
+    };
+    let text = String::from_utf8_lossy(&partial[..valid]).into_owned();
+    partial.drain(..valid);
+    text
+}
+
+/// Parses the size of a resize event, `<cols>x<rows>`.
+pub(crate) fn parse_size(data: &str) -> Option<(u16, u16)> {
+    let (cols, rows) = data.split_once('x')?;
+    let size = (cols.parse().ok()?, rows.parse().ok()?);
+    if size.0 == 0 || size.1 == 0 {
+        return None;
+    }
+    Some(size)
+}
+
+/// Escapes `s` for use inside a JSON string.
+pub(crate) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ptyme-asciicast-{}-{}", std::process::id(), name))
+    }
+
+    fn winsize(cols: u16, rows: u16) -> Winsize {
+        Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+
+    fn open_str(name: &str, contents: &str) -> Result<Cast, Box<dyn Error>> {
+        let path = temp_path(name);
+        fs::write(&path, contents).unwrap();
+        let cast = Cast::open(&path);
+        fs::remove_file(&path).unwrap();
+        cast
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round-trip");
+        let cmd = ["printf".to_string(), "\"quoted\" \\ back".to_string()];
+        let mut recorder = Recorder::create(&path, &winsize(100, 30), &cmd, true).unwrap();
+        recorder
+            .output(b"\x1b[1mbold\x1b[0m\r\n\ttab \"q\" \\ \x07\x7f")
+            .unwrap();
+        // A character split across two reads is recorded once it is complete.
+        recorder.output(b"caf\xc3").unwrap();
+        recorder.output(b"\xa9 \xf0\x9f\x98\x80").unwrap();
+        recorder.input(b"ls\r").unwrap();
+        recorder.resize(&winsize(120, 40)).unwrap();
+        recorder.output(b"bad \xff byte").unwrap();
+        drop(recorder);
+
+        let cast = Cast::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((cast.width, cast.height), (100, 30));
+        let events: Vec<_> = cast
+            .events
+            .iter()
+            .map(|e| (e.code.as_str(), e.data.as_str()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("o", "\x1b[1mbold\x1b[0m\r\n\ttab \"q\" \\ \x07\x7f"),
+                ("o", "caf"),
+                ("o", "é 😀"),
+                ("i", "ls\r"),
+                ("r", "120x40"),
+                ("o", "bad \u{fffd} byte"),
+            ]
+        );
+        assert!(cast.events.windows(2).all(|w| w[0].time <= w[1].time));
+    }
+
+    #[test]
+    fn input_is_only_recorded_if_asked() {
+        let path = temp_path("no-input");
+        let mut recorder = Recorder::create(&path, &winsize(80, 24), &[], false).unwrap();
+        recorder.input(b"secret\r").unwrap();
+        recorder.output(b"out").unwrap();
+        drop(recorder);
+
+        let cast = Cast::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(cast.events.len(), 1);
+        assert_eq!(cast.events[0].code, "o");
+    }
+
+    #[test]
+    fn reads_events() {
+        let cast = open_str(
+            "events",
+            "{\"version\": 2, \"width\": 20, \"height\": 3}\n\
+             [0.5, \"o\", \"a\\u001b[0m\"]\n\
+             \n\
+             [1.25, \"r\", \"30x4\"]\n",
+        )
+        .unwrap();
+        assert_eq!((cast.width, cast.height), (20, 3));
+        assert_eq!(cast.events.len(), 2);
+        assert_eq!(cast.events[0].time, 0.5);
+        assert_eq!(cast.events[0].data, "a\x1b[0m");
+        assert_eq!(cast.events[1].code, "r");
+    }
+
+    #[test]
+    fn malformed() {
+        for (name, contents) in &[
+            ("empty", ""),
+            ("not-json", "asciicast\n"),
+            (
+                "version-1",
+                "{\"version\": 1, \"width\": 80, \"height\": 24}\n",
+            ),
+            ("no-version", "{\"width\": 80, \"height\": 24}\n"),
+            ("no-width", "{\"version\": 2, \"height\": 24}\n"),
+            (
+                "huge-width",
+                "{\"version\": 2, \"width\": 4000000000, \"height\": 24}\n",
+            ),
+            (
+                "zero-width",
+                "{\"version\": 2, \"width\": 0, \"height\": 24}\n",
+            ),
+            (
+                "negative-height",
+                "{\"version\": 2, \"width\": 80, \"height\": -1}\n",
+            ),
+            (
+                "fractional-height",
+                "{\"version\": 2, \"width\": 80, \"height\": 2.5}\n",
+            ),
+            (
+                "bad-resize",
+                "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0.5, \"r\", \"80x99999\"]\n",
+            ),
+            ("not-object", "[2, 80, 24]\n"),
+            (
+                "short-event",
+                "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0.5, \"o\"]\n",
+            ),
+            (
+                "bad-time",
+                "{\"version\": 2, \"width\": 80, \"height\": 24}\n[\"0.5\", \"o\", \"x\"]\n",
+            ),
+            (
+                "bad-event",
+                "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0.5, \"o\", \"x\"\n",
+            ),
+        ] {
+            assert!(open_str(name, contents).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("80x24"), Some((80, 24)));
+        assert_eq!(parse_size("80"), None);
+        assert_eq!(parse_size("x24"), None);
+        assert_eq!(parse_size("80x-1"), None);
+        assert_eq!(parse_size("0x24"), None);
+        assert_eq!(parse_size("80x65536"), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Audit logging of everything typed into a session.
+//!
+//! Every chunk of input is logged on a line of its own, with the local time
+//! it was typed at, the seconds since the session started, the raw bytes in
+//! hex, and a rendering of them with control keys in caret notation:
+//!
+//! ```text
+//! 2020-05-04 11:26:39+02:00 +1.204311 6c73200d ls ^M
+//! 2020-05-04 11:26:41+02:00 +3.016250 1b5b41 ^[[A
+//! ```
+//!
+//! Input typed while the program has echo turned off, such as at password
+//! prompts, can be masked. Its bytes are left out and every character other
+//! than Enter is rendered as `*`.
+
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::str;
+use std::time::Instant;
+
+use nix::sys::termios::{self, LocalFlags};
+
+use crate::filter::{keep_error, Filter};
+use crate::typescript::local_time;
+
+/// Writes everything typed into a session to an audit log.
+pub struct InputLog {
+    file: BufWriter<File>,
+    start: Instant,
+    mask_silent: bool,
+    // The PTY whose echo decides what is masked, when used as a filter.
+    pty_master: Option<RawFd>,
+    // The first error writing the file as a filter, for `take_error`.
+    error: Option<io::Error>,
+}
+
+impl InputLog {
+    /// Opens the audit log at `path`, appending to it if it exists.
+    /// Input typed while echo is off is masked if `mask_silent` is set.
+    /// A new log is only readable by its owner, as input may include secrets.
+    pub fn open(path: impl AsRef<Path>, mask_silent: bool) -> Result<InputLog, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(InputLog {
+            file: BufWriter::new(file),
+            start: Instant::now(),
+            mask_silent,
+            pty_master: None,
+            error: None,
+        })
+    }
+
+    /// Whether input is masked while echo is off, so callers only need to
+    /// find out whether it is if so.
+    pub fn masks_silent(&self) -> bool {
+        self.mask_silent
+    }
+
+    /// Logs `data` typed into the session, while the echo of the program's
+    /// terminal was on or off.
+    pub fn input(&mut self, data: &[u8], echo: bool) -> io::Result<()> {
+        let masked = self.mask_silent && !echo;
+        let (raw, rendered) = if masked {
+            ("masked".to_string(), mask(data))
+        } else {
+            (hex(data), render(data))
+        };
+        writeln!(
+            self.file,
+            "{} +{:.6} {} {}",
+            local_time(),
+            self.start.elapsed().as_secs_f64(),
+            raw,
+            rendered
+        )?;
+        self.file.flush()
+    }
+}
+
+/// Logs the input as it passes through, without changing anything. Input is
+/// masked while the session's terminal has echo turned off.
+impl Filter for InputLog {
+    fn on_start(&mut self, pty_master: RawFd) {
+        self.pty_master = Some(pty_master);
+    }
+
+    fn on_input(&mut self, data: &[u8]) -> Vec<u8> {
+        // Whether the program shows what is typed, or is asking for a
+        // password or the like.
+        let echo = !self.mask_silent
+            || self.pty_master.is_none_or(|pty_master| {
+                termios::tcgetattr(pty_master)
+                    .map_or(true, |t| t.local_flags.contains(LocalFlags::ECHO))
+            });
+        let res = self.input(data, echo);
+        keep_error(&mut self.error, res);
+        data.to_vec()
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+/// Returns `data` in lowercase hex.
+fn hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(data.len() * 2);
+    for b in data {
+        let _ = write!(hex, "{:02x}", b);
+    }
+    hex
+}
+
+/// Renders `data` readably: control characters in caret notation, such as
+/// `^C` or `^[`, and bytes that aren't valid UTF-8 as `\xNN`.
+fn render(data: &[u8]) -> String {
+    let mut out = String::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (valid, invalid) = match str::from_utf8(rest) {
+            Ok(valid) => (valid, &[][..]),
+            Err(err) => {
+                let (valid, after) = rest.split_at(err.valid_up_to());
+                let len = err.error_len().unwrap_or(after.len());
+                (str::from_utf8(valid).unwrap(), &after[..len])
+            }
+        };
+        for c in valid.chars() {
+            match c {
+                '\x00'..='\x1f' => {
+                    out.push('^');
+                    out.push((c as u8 ^ 0x40) as char);
+                }
+                '\x7f' => out.push_str("^?"),
+                c => out.push(c),
+            }
+        }
+        for b in invalid {
+            let _ = write!(out, "\\x{:02x}", b);
+        }
+        rest = &rest[valid.len() + invalid.len()..];
+    }
+    out
+}
+
+/// Renders `data` with every character but Enter masked.
+fn mask(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .chars()
+        .map(|c| match c {
+            '\r' => "^M",
+            '\n' => "^J",
+            _ => "*",
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn rendering() {
+        let cases: &[(&[u8], &str, &str)] = &[
+            (b"ls -l\r", "6c73202d6c0d", "ls -l^M"),
+            (b"\x03\x04\x1a", "03041a", "^C^D^Z"),
+            (b"\x00\t\n\x7f", "00090a7f", "^@^I^J^?"),
+            (b"\x1b[A\x1bOP", "1b5b411b4f50", "^[[A^[OP"),
+            ("é€😀".as_bytes(), "c3a9e282acf09f9880", "é€😀"),
+            // Bytes that aren't valid UTF-8, including a character cut short.
+            (b"a\xffb\xe2\x82", "61ff62e282", "a\\xffb\\xe2\\x82"),
+            (b"\xe2\x82\xe2\x82\xac", "e282e282ac", "\\xe2\\x82€"),
+            (b"", "", ""),
+        ];
+        for &(input, hexed, rendered) in cases {
+            assert_eq!(hex(input), hexed, "{:?}", input);
+            assert_eq!(render(input), rendered, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn masking() {
+        assert_eq!(mask(b"hunter2\r"), "*******^M");
+        assert_eq!(mask("pässwörd\n".as_bytes()), "********^J");
+        assert_eq!(mask(b"\x1b[A\x7f"), "****");
+    }
+
+    #[test]
+    fn masked_while_echo_is_off() {
+        let path = env::temp_dir().join(format!("ptyme-audit-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut log = InputLog::open(&path, true).unwrap();
+        log.input(b"su\r", true).unwrap();
+        log.input(b"secret\r", false).unwrap();
+        let mut unmasked = InputLog::open(&path, false).unwrap();
+        unmasked.input(b"ok\r", false).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        // Each line is the time, the seconds since the start, then the input.
+        let entries: Vec<String> = text
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.splitn(4, ' ').collect();
+                assert_eq!(fields.len(), 4, "{:?}", line);
+                assert!(fields[2].starts_with('+'), "{:?}", line);
+                assert!(fields[2][1..].parse::<f64>().is_ok(), "{:?}", line);
+                fields[3].to_string()
+            })
+            .collect();
+        assert_eq!(entries, ["73750d su^M", "masked ******^M", "6f6b0d ok^M"]);
+    }
+}
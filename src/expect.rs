@@ -0,0 +1,222 @@
+//! Automation of interactive programs, in the style of expect(1).
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::unistd::{self, Pid};
+
+use crate::pty::{reap_hung_up, PtyPair};
+use crate::regex::{Captures, Regex};
+use crate::term::Winsize;
+
+const PTY_MASTER: Token = Token(0);
+
+/// Output kept around to be matched against, by default. Older output that
+/// hasn't been matched by then is dropped, like expect's `match_max`.
+pub const DEFAULT_MATCH_MAX: usize = 8192;
+
+/// Why an expectation wasn't met.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectError {
+    /// The timeout passed without a match.
+    Timeout,
+    /// The program closed the terminal without a match.
+    Eof,
+}
+
+impl fmt::Display for ExpectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExpectError::Timeout => f.write_str("timed out"),
+            ExpectError::Eof => f.write_str("end of output"),
+        }
+    }
+}
+
+impl Error for ExpectError {}
+
+/// A program running on a PTY, driven by sending it input and waiting for
+/// its output to match patterns.
+///
+/// Errors from `expect` and `expect_eof` that are an `ExpectError` can be
+/// told apart from I/O errors with `downcast_ref`.
+pub struct Expect {
+    pty_pair: PtyPair,
+    child: Pid,
+    poll: Poll,
+    events: Events,
+    /// Output not matched yet.
+    buffer: Vec<u8>,
+    match_max: usize,
+    eof: bool,
+    log: Option<Box<dyn Write>>,
+}
+
+impl Expect {
+    /// Runs `cmd` on a new PTY of size `winsize`.
+    pub fn spawn(cmd: &[String], winsize: &Winsize) -> Result<Expect, Box<dyn Error>> {
+        let pty_pair = PtyPair::open()?;
+        pty_pair.resize(winsize)?;
+        let child = pty_pair.spawn(cmd)?;
+        Expect::new(pty_pair, child)
+    }
+
+    /// Drives the `child` already running on `pty_pair`.
+    pub fn new(pty_pair: PtyPair, child: Pid) -> Result<Expect, Box<dyn Error>> {
+        let master = pty_pair.master.as_raw_fd();
+        fcntl::fcntl(master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+
+        let poll = Poll::new()?;
+        poll.registry()
+            .register(&mut SourceFd(&master), PTY_MASTER, Interest::READABLE)?;
+
+        Ok(Expect {
+            pty_pair,
+            child,
+            poll,
+            events: Events::with_capacity(16),
+            buffer: Vec::new(),
+            match_max: DEFAULT_MATCH_MAX,
+            eof: false,
+            log: None,
+        })
+    }
+
+    /// Copies all output of the program to `log` as it is read.
+    pub fn set_log(&mut self, log: Option<Box<dyn Write>>) {
+        self.log = log;
+    }
+
+    /// Sets how much unmatched output is kept around to be matched against.
+    pub fn set_match_max(&mut self, match_max: usize) {
+        self.match_max = match_max;
+    }
+
+    /// Output read but not matched yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Whether the program has closed the terminal.
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    fn master(&self) -> RawFd {
+        self.pty_pair.master.as_raw_fd()
+    }
+
+    /// Sends `data` to the program, as if it was typed.
+    pub fn send(&mut self, mut data: &[u8]) -> Result<(), Box<dyn Error>> {
+        while !data.is_empty() {
+            match unistd::write(self.master(), data) {
+                Ok(n) => data = &data[n..],
+                Err(nix::Error::Sys(Errno::EINTR)) => {}
+                // The program isn't reading its input. Keep reading its
+                // output in the meantime, in case it is waiting for that.
+                Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                    self.fill(Some(Duration::from_millis(10)))?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the output matches `re` and returns the captured groups.
+    /// Output up to the end of the match is consumed.
+    /// Waits forever if `timeout` is `None`.
+    pub fn expect(
+        &mut self,
+        re: &Regex,
+        timeout: Option<Duration>,
+    ) -> Result<Captures, Box<dyn Error>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(captures) = re.captures(&self.buffer) {
+                self.buffer.drain(..captures.end());
+                return Ok(captures);
+            }
+            if self.eof {
+                return Err(ExpectError::Eof.into());
+            }
+            self.fill(remaining(deadline)?)?;
+        }
+    }
+
+    /// Waits until the program closes the terminal, and returns the output
+    /// that wasn't matched.
+    /// Waits forever if `timeout` is `None`.
+    pub fn expect_eof(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, Box<dyn Error>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        while !self.eof {
+            self.fill(remaining(deadline)?)?;
+        }
+        Ok(self.buffer.drain(..).collect())
+    }
+
+    /// Closes the terminal, which hangs up the program unless it already
+    /// exited, and returns its exit status. A program that doesn't exit
+    /// when hung up is killed, which is returned as an error.
+    pub fn close(self) -> Result<i32, Box<dyn Error>> {
+        let Expect {
+            pty_pair, child, ..
+        } = self;
+        drop(pty_pair);
+        reap_hung_up(child)
+    }
+
+    /// Waits up to `timeout` for output, and reads everything available.
+    fn fill(&mut self, timeout: Option<Duration>) -> Result<(), Box<dyn Error>> {
+        match self.poll.poll(&mut self.events, timeout) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            res => res?,
+        }
+
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match unistd::read(self.master(), &mut buf) {
+                // Everything on the slave side is gone.
+                Ok(0) | Err(nix::Error::Sys(Errno::EIO)) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => n,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+                Err(err) => return Err(err.into()),
+            };
+            if let Some(log) = self.log.as_mut() {
+                log.write_all(&buf[..n])?;
+                log.flush()?;
+            }
+            self.buffer.extend_from_slice(&buf[..n]);
+        }
+
+        let excess = self.buffer.len().saturating_sub(self.match_max);
+        self.buffer.drain(..excess);
+        Ok(())
+    }
+}
+
+/// Returns the time left until `deadline`, or a timeout error if it passed.
+pub(crate) fn remaining(deadline: Option<Instant>) -> Result<Option<Duration>, ExpectError> {
+    match deadline {
+        None => Ok(None),
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                Err(ExpectError::Timeout)
+            } else {
+                Ok(Some(deadline - now))
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! Filters that the data passing through a session goes through, to log,
+//! record, rewrite or hold back what is typed and what the program writes.
+//!
+//! A filter sees every chunk of data in either direction and returns what
+//! to pass on in its place, so filters that only watch, such as logs,
+//! return the data as it is:
+//!
+//! ```
+//! use ptyme::{Filter, Filters};
+//!
+//! /// Hides a secret from the terminal, and anything after it in the chain.
+//! struct Redact(&'static str);
+//!
+//! impl Filter for Redact {
+//!     fn on_output(&mut self, data: &[u8]) -> Vec<u8> {
+//!         String::from_utf8_lossy(data)
+//!             .replace(self.0, "[redacted]")
+//!             .into_bytes()
+//!     }
+//! }
+//!
+//! let mut filters = Filters::new();
+//! filters.push(Redact("hunter2"));
+//! ```
+//!
+//! Data is handed to filters in the chunks it is read in, which may split
+//! lines, escape sequences and UTF-8 encoded characters anywhere.
+
+use std::fmt;
+use std::io;
+use std::os::unix::io::RawFd;
+
+use crate::pty::ExitStatus;
+use crate::term::Winsize;
+
+/// Inspects or transforms the data passing through a session.
+///
+/// Filters can't fail the session from `on_input` or `on_output`. A filter
+/// that runs into an error, such as a log that can't be written, keeps it
+/// for `take_error` instead, and the session ends with it.
+pub trait Filter {
+    /// Called once, before any data passes through, with the PTY master of
+    /// the session, such as to look at its terminal settings later on.
+    fn on_start(&mut self, _pty_master: RawFd) {}
+
+    /// Called with data typed into the session. Returns what is passed on to
+    /// the program instead, the data as it is by default.
+    fn on_input(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    /// Called with data the program wrote to the terminal. Returns what is
+    /// passed on to the terminal instead, the data as it is by default.
+    fn on_output(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    /// Called when the terminal of the session is resized.
+    fn on_resize(&mut self, _winsize: &Winsize) {}
+
+    /// Called once the session ended, and how the program exited is known.
+    fn on_exit(&mut self, _status: &ExitStatus) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the error the filter ran into since it was last asked, if any.
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+}
+
+/// Keeps the first error of `res` in `error`, for `Filter::take_error`.
+pub(crate) fn keep_error(error: &mut Option<io::Error>, res: io::Result<()>) {
+    if error.is_none() {
+        *error = res.err();
+    }
+}
+
+/// A chain of filters. Input and output alike go through the filters in the
+/// order they were added, each getting what the one before it passed on.
+/// Once a filter passes on nothing, the filters after it aren't called.
+#[derive(Default)]
+pub struct Filters {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Filters({} filters)", self.filters.len())
+    }
+}
+
+impl Filters {
+    /// Returns an empty chain, which passes everything on as it is.
+    pub fn new() -> Filters {
+        Filters::default()
+    }
+
+    /// Adds `filter` to the end of the chain.
+    pub fn push(&mut self, filter: impl Filter + 'static) -> &mut Filters {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Whether there are no filters in the chain.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub(crate) fn start(&mut self, pty_master: RawFd) {
+        for filter in &mut self.filters {
+            filter.on_start(pty_master);
+        }
+    }
+
+    /// Passes `data` typed into the session through the chain.
+    pub(crate) fn input(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.pass(data, |filter, data| filter.on_input(data))
+    }
+
+    /// Passes `data` written by the program through the chain.
+    pub(crate) fn output(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.pass(data, |filter, data| filter.on_output(data))
+    }
+
+    fn pass(
+        &mut self,
+        data: &[u8],
+        mut on_data: impl FnMut(&mut dyn Filter, &[u8]) -> Vec<u8>,
+    ) -> io::Result<Vec<u8>> {
+        let mut data = data.to_vec();
+        for filter in &mut self.filters {
+            data = on_data(filter.as_mut(), &data);
+            if let Some(err) = filter.take_error() {
+                return Err(err);
+            }
+            if data.is_empty() {
+                break;
+            }
+        }
+        Ok(data)
+    }
+
+    pub(crate) fn resize(&mut self, winsize: &Winsize) -> io::Result<()> {
+        for filter in &mut self.filters {
+            filter.on_resize(winsize);
+            if let Some(err) = filter.take_error() {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets every filter know the session ended, and returns the first error
+    /// any of them ran into.
+    pub(crate) fn exit(&mut self, status: &ExitStatus) -> io::Result<()> {
+        let mut error = None;
+        for filter in &mut self.filters {
+            keep_error(&mut error, filter.on_exit(status));
+            if let Some(err) = filter.take_error() {
+                keep_error(&mut error, Err(err));
+            }
+        }
+        error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Calls = Rc<RefCell<Vec<String>>>;
+
+    /// Appends its name to the data it passes on, and records its calls.
+    struct Tag {
+        name: &'static str,
+        calls: Calls,
+        error: Option<io::Error>,
+    }
+
+    impl Tag {
+        fn new(name: &'static str, calls: &Calls) -> Tag {
+            Tag {
+                name,
+                calls: calls.clone(),
+                error: None,
+            }
+        }
+
+        fn call(&self, call: String) {
+            self.calls
+                .borrow_mut()
+                .push(format!("{} {}", self.name, call));
+        }
+    }
+
+    impl Filter for Tag {
+        fn on_input(&mut self, data: &[u8]) -> Vec<u8> {
+            self.call(format!("input {}", String::from_utf8_lossy(data)));
+            [data, self.name.as_bytes()].concat()
+        }
+
+        fn on_output(&mut self, data: &[u8]) -> Vec<u8> {
+            self.call(format!("output {}", String::from_utf8_lossy(data)));
+            [data, self.name.as_bytes()].concat()
+        }
+
+        fn on_resize(&mut self, winsize: &Winsize) {
+            self.call(format!("resize {}x{}", winsize.ws_col, winsize.ws_row));
+        }
+
+        fn on_exit(&mut self, status: &ExitStatus) -> io::Result<()> {
+            self.call(format!("exit {}", status.code));
+            Ok(())
+        }
+
+        fn take_error(&mut self) -> Option<io::Error> {
+            self.error.take()
+        }
+    }
+
+    /// Holds back all output, and fails on every call if `fail` is set.
+    struct Swallow {
+        fail: bool,
+        error: Option<io::Error>,
+    }
+
+    impl Swallow {
+        fn fail(&mut self, what: &str) {
+            if self.fail {
+                let res = Err(io::Error::other(what));
+                keep_error(&mut self.error, res);
+            }
+        }
+    }
+
+    impl Filter for Swallow {
+        fn on_output(&mut self, _data: &[u8]) -> Vec<u8> {
+            self.fail("output");
+            Vec::new()
+        }
+
+        fn on_resize(&mut self, _winsize: &Winsize) {
+            self.fail("resize");
+        }
+
+        fn on_exit(&mut self, _status: &ExitStatus) -> io::Result<()> {
+            self.fail("exit");
+            Ok(())
+        }
+
+        fn take_error(&mut self) -> Option<io::Error> {
+            self.error.take()
+        }
+    }
+
+    fn winsize() -> Winsize {
+        Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+
+    #[test]
+    fn data_goes_through_in_order() {
+        let calls = Calls::default();
+        let mut filters = Filters::new();
+        filters
+            .push(Tag::new("a", &calls))
+            .push(Tag::new("b", &calls));
+
+        assert_eq!(filters.input(b"in").unwrap(), b"inab");
+        assert_eq!(filters.output(b"out").unwrap(), b"outab");
+        assert_eq!(
+            *calls.borrow(),
+            ["a input in", "b input ina", "a output out", "b output outa"]
+        );
+        assert_eq!(Filters::new().output(b"as is").unwrap(), b"as is");
+    }
+
+    #[test]
+    fn nothing_passed_on_ends_the_chain() {
+        let calls = Calls::default();
+        let mut filters = Filters::new();
+        filters
+            .push(Tag::new("a", &calls))
+            .push(Swallow {
+                fail: false,
+                error: None,
+            })
+            .push(Tag::new("b", &calls));
+
+        assert_eq!(filters.output(b"out").unwrap(), b"");
+        // Input goes all the way through, as the filter only swallows output.
+        assert_eq!(filters.input(b"in").unwrap(), b"inab");
+        assert_eq!(
+            *calls.borrow(),
+            ["a output out", "a input in", "b input ina"]
+        );
+    }
+
+    #[test]
+    fn resize_and_exit_reach_every_filter() {
+        let calls = Calls::default();
+        let mut filters = Filters::new();
+        filters
+            .push(Tag::new("a", &calls))
+            .push(Swallow {
+                fail: false,
+                error: None,
+            })
+            .push(Tag::new("b", &calls));
+
+        filters.resize(&winsize()).unwrap();
+        filters.exit(&ExitStatus::exited(3)).unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            ["a resize 80x24", "b resize 80x24", "a exit 3", "b exit 3"]
+        );
+    }
+
+    #[test]
+    fn errors_are_surfaced() {
+        let calls = Calls::default();
+        let mut failing = Tag::new("a", &calls);
+        failing.error = Some(io::Error::other("first"));
+        let mut filters = Filters::new();
+        filters
+            .push(failing)
+            .push(Swallow {
+                fail: true,
+                error: None,
+            })
+            .push(Tag::new("b", &calls));
+
+        // The first error ends the chain, and the next call goes on.
+        assert_eq!(filters.output(b"out").unwrap_err().to_string(), "first");
+        assert_eq!(filters.output(b"out").unwrap_err().to_string(), "output");
+        assert_eq!(
+            filters.resize(&winsize()).unwrap_err().to_string(),
+            "resize"
+        );
+        // Every filter hears of the exit, and the first error is returned.
+        assert_eq!(
+            filters
+                .exit(&ExitStatus::exited(0))
+                .unwrap_err()
+                .to_string(),
+            "exit"
+        );
+        assert_eq!(
+            *calls.borrow(),
+            [
+                "a output out",
+                "a output out",
+                "a resize 80x24",
+                "a exit 0",
+                "b exit 0"
+            ]
+        );
+    }
+}
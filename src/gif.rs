@@ -0,0 +1,352 @@
+//! A minimal encoder of animated GIFs, with a global palette of 256 colors.
+//!
+//! Frames after the first only need to cover the part of the image that
+//! changed, and are drawn over what is already there.
+//!
+//! See <https://www.w3.org/Graphics/GIF/spec-gif89a.txt>.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Bits per pixel of the image data, enough for 256 colors.
+const MIN_CODE_SIZE: u8 = 8;
+const CLEAR_CODE: u16 = 1 << MIN_CODE_SIZE;
+const END_CODE: u16 = CLEAR_CODE + 1;
+/// Codes are at most 12 bits long, the table is started over when full.
+const MAX_CODES: u16 = 1 << 12;
+
+/// Writes the frames of an animated GIF, looping forever.
+pub(crate) struct GifWriter<W: Write> {
+    w: W,
+}
+
+/// The part of a frame that is drawn over the frames before it.
+pub(crate) struct Rect {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Writes the header of a `width` by `height` animation to `w`, with
+    /// the colors in `palette`.
+    pub fn new(mut w: W, width: u16, height: u16, palette: &[[u8; 3]; 256]) -> io::Result<Self> {
+        w.write_all(b"GIF89a")?;
+        w.write_all(&width.to_le_bytes())?;
+        w.write_all(&height.to_le_bytes())?;
+        // A global color table of 256 colors of 8 bits each, background
+        // color 0 and square pixels.
+        w.write_all(&[0xf7, 0, 0])?;
+        for color in palette.iter() {
+            w.write_all(color)?;
+        }
+        // The Netscape extension, for looping forever.
+        w.write_all(&[0x21, 0xff, 0x0b])?;
+        w.write_all(b"NETSCAPE2.0")?;
+        w.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+        Ok(GifWriter { w })
+    }
+
+    /// Writes a frame covering `rect`, made up of the palette `indices` of
+    /// its pixels row by row, shown for `delay` hundredths of a second.
+    pub fn frame(&mut self, rect: &Rect, indices: &[u8], delay: u16) -> io::Result<()> {
+        assert_eq!(indices.len(), rect.width as usize * rect.height as usize);
+
+        // Graphic control: leave the frame in place for the next one to be
+        // drawn over, with no transparent color.
+        self.w.write_all(&[0x21, 0xf9, 0x04, 0x04])?;
+        self.w.write_all(&delay.to_le_bytes())?;
+        self.w.write_all(&[0x00, 0x00])?;
+
+        self.w.write_all(&[0x2c])?;
+        for n in &[rect.left, rect.top, rect.width, rect.height] {
+            self.w.write_all(&n.to_le_bytes())?;
+        }
+        // No local color table, not interlaced.
+        self.w.write_all(&[0x00, MIN_CODE_SIZE])?;
+
+        // The compressed data goes in sub-blocks of up to 255 bytes.
+        for block in lzw(indices).chunks(255) {
+            self.w.write_all(&[block.len() as u8])?;
+            self.w.write_all(block)?;
+        }
+        self.w.write_all(&[0x00])
+    }
+
+    /// Writes the trailer, and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.write_all(&[0x3b])?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+/// Packs codes of varying length into bytes, least significant bit first.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    len: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.bits |= u32::from(code) << self.len;
+        self.len += size;
+        while self.len >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Compresses `indices` with the variable length LZW of GIF.
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter {
+        out: Vec::new(),
+        bits: 0,
+        len: 0,
+    };
+    // Codes of the strings seen so far, by the code of the string without
+    // its last index, and that index.
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = END_CODE + 1;
+    let mut size = MIN_CODE_SIZE + 1;
+
+    out.write(CLEAR_CODE, size);
+    let mut indices = indices.iter();
+    let mut prefix = match indices.next() {
+        Some(&index) => u16::from(index),
+        None => {
+            out.write(END_CODE, size);
+            return out.finish();
+        }
+    };
+
+    for &index in indices {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        out.write(prefix, size);
+        table.insert((prefix, index), next);
+        next += 1;
+        // The decoder adds codes a step behind, so codes get longer once
+        // the next one no longer fits.
+        if next > 1 << size && size < 12 {
+            size += 1;
+        }
+        if next == MAX_CODES {
+            out.write(CLEAR_CODE, size);
+            table.clear();
+            next = END_CODE + 1;
+            size = MIN_CODE_SIZE + 1;
+        }
+        prefix = u16::from(index);
+    }
+
+    out.write(prefix, size);
+    // The decoder adds a code for the last one too.
+    if next == 1 << size && size < 12 {
+        size += 1;
+    }
+    out.write(END_CODE, size);
+    out.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decompresses GIF LZW data, as a decoder following the spec would.
+    /// Returns the indices and how often the table was cleared after the
+    /// first clear code.
+    fn unlzw(data: &[u8]) -> (Vec<u8>, usize) {
+        let mut bits = data
+            .iter()
+            .flat_map(|&byte| (0..8).map(move |i| u16::from(byte >> i & 1)));
+        let mut read = |size: u8| -> u16 {
+            (0..size).fold(0, |code, i| {
+                code | bits.next().expect("missing end code") << i
+            })
+        };
+
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = MIN_CODE_SIZE + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        let mut clears = 0;
+        assert_eq!(read(size), CLEAR_CODE);
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..=255).map(|index| vec![index]).collect();
+            // The clear and end codes.
+            table.push(Vec::new());
+            table.push(Vec::new());
+        };
+        reset(&mut table);
+
+        loop {
+            let code = read(size);
+            if code == CLEAR_CODE {
+                reset(&mut table);
+                size = MIN_CODE_SIZE + 1;
+                prev = None;
+                clears += 1;
+                continue;
+            }
+            if code == END_CODE {
+                return (out, clears);
+            }
+            let entry = match (table.get(code as usize), &prev) {
+                (Some(entry), _) => entry.clone(),
+                // The string that is about to be added.
+                (None, Some(prev)) if code as usize == table.len() => {
+                    let mut entry = prev.clone();
+                    entry.push(prev[0]);
+                    entry
+                }
+                _ => panic!("invalid code {}", code),
+            };
+            out.extend_from_slice(&entry);
+            if let Some(mut prev) = prev.take() {
+                if table.len() < MAX_CODES as usize {
+                    prev.push(entry[0]);
+                    table.push(prev);
+                }
+            }
+            prev = Some(entry);
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+        }
+    }
+
+    /// Bytes that hardly repeat, so that the table fills up quickly.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 12345u32;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lzw_known_codes() {
+        // Clear, 0, then the code for 0 0 added after it, 0 and end, all
+        // 9 bits long.
+        assert_eq!(lzw(&[0, 0, 0, 0]), [0x00, 0x01, 0x08, 0x04, 0x10, 0x10]);
+        // Just clear and end.
+        assert_eq!(lzw(&[]), [0x00, 0x03, 0x02]);
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let mut cases = vec![
+            vec![7],
+            vec![1, 2, 1, 2, 1, 2, 1],
+            vec![0; 100_000],
+            (0..=255).cycle().take(5000).collect(),
+        ];
+        // Lengths around the points where codes get longer.
+        for len in &[
+            250, 254, 255, 256, 257, 510, 511, 512, 513, 1790, 1791, 1792, 1793,
+        ] {
+            cases.push(noise(*len));
+        }
+        for indices in &cases {
+            let (decoded, _) = unlzw(&lzw(indices));
+            assert!(decoded == *indices, "length {}", indices.len());
+        }
+    }
+
+    #[test]
+    fn lzw_clears_full_table() {
+        // Each new code takes about a byte, so this fills the table of 12
+        // bit codes several times.
+        let indices = noise(20_000);
+        let (decoded, clears) = unlzw(&lzw(&indices));
+        assert!(decoded == indices);
+        assert!(clears >= 4, "{} clears", clears);
+
+        // And right up to the end of a full table, while a code still has to
+        // be written.
+        for len in 3835..3842 {
+            let indices = noise(len);
+            assert!(unlzw(&lzw(&indices)).0 == indices, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn animation() {
+        let mut palette = [[0; 3]; 256];
+        palette[1] = [255, 0, 0];
+        let mut gif = GifWriter::new(Vec::new(), 3, 2, &palette).unwrap();
+        let first = [0, 1, 0, 1, 0, 1];
+        let rect = Rect {
+            left: 0,
+            top: 0,
+            width: 3,
+            height: 2,
+        };
+        gif.frame(&rect, &first, 50).unwrap();
+        let rect = Rect {
+            left: 1,
+            top: 1,
+            width: 1,
+            height: 1,
+        };
+        gif.frame(&rect, &[1], 7).unwrap();
+        let data = gif.finish().unwrap();
+
+        assert_eq!(&data[..6], b"GIF89a");
+        assert_eq!(&data[6..13], &[3, 0, 2, 0, 0xf7, 0, 0]);
+        assert_eq!(&data[16..19], &[255, 0, 0]);
+        let mut rest = &data[13 + 256 * 3..];
+        assert_eq!(&rest[..3], &[0x21, 0xff, 0x0b]);
+        assert_eq!(&rest[3..14], b"NETSCAPE2.0");
+        rest = &rest[19..];
+
+        let mut frames = Vec::new();
+        while rest[0] != 0x3b {
+            // Graphic control extension, then the image descriptor.
+            assert_eq!(&rest[..4], &[0x21, 0xf9, 0x04, 0x04]);
+            let delay = u16::from_le_bytes([rest[4], rest[5]]);
+            assert_eq!(rest[8], 0x2c);
+            let desc: Vec<u16> = rest[9..17]
+                .chunks(2)
+                .map(|n| u16::from_le_bytes([n[0], n[1]]))
+                .collect();
+            assert_eq!(&rest[17..19], &[0x00, MIN_CODE_SIZE]);
+            rest = &rest[19..];
+            let mut lzw_data = Vec::new();
+            loop {
+                let len = rest[0] as usize;
+                lzw_data.extend_from_slice(&rest[1..=len]);
+                rest = &rest[len + 1..];
+                if len == 0 {
+                    break;
+                }
+            }
+            frames.push((delay, desc, unlzw(&lzw_data).0));
+        }
+        assert_eq!(rest, &[0x3b]);
+        assert_eq!(
+            frames,
+            [
+                (50, vec![0, 0, 3, 2], first.to_vec()),
+                (7, vec![1, 1, 1, 1], vec![1]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Running programs on a PTY with an emulated screen, to see what they
+//! would show on a terminal, such as for testing full-screen programs.
+
+use std::error::Error;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::unistd::{self, Pid};
+
+use crate::expect::{remaining, ExpectError};
+use crate::pty::{reap_hung_up, PtyPair};
+use crate::screen::Screen;
+use crate::term::{self, Winsize};
+
+const PTY_MASTER: Token = Token(0);
+
+/// Most output `update` reads at once, so that it returns in time even
+/// while a program writes faster than its screen can be updated.
+const MAX_UPDATE: usize = 64 * 1024;
+
+/// A program running on a PTY, with its output shown on an emulated screen
+/// rather than a real terminal.
+///
+/// Errors from `wait_for` that are an `ExpectError` can be told apart from
+/// I/O errors with `downcast_ref`.
+pub struct HeadlessTerm {
+    pty_pair: PtyPair,
+    child: Pid,
+    poll: Poll,
+    events: Events,
+    screen: Screen,
+    eof: bool,
+    // Whether `update` stopped reading before it ran out of output, and
+    // won't be told about the rest.
+    pending: bool,
+}
+
+impl HeadlessTerm {
+    /// Runs `cmd` on a new PTY, with a screen of size `winsize`.
+    pub fn spawn(cmd: &[String], winsize: &Winsize) -> Result<HeadlessTerm, Box<dyn Error>> {
+        let pty_pair = PtyPair::open()?;
+        pty_pair.resize(winsize)?;
+        let child = pty_pair.spawn(cmd)?;
+        HeadlessTerm::new(pty_pair, child)
+    }
+
+    /// Emulates the screen of the `child` already running on `pty_pair`,
+    /// which is as big as the PTY.
+    pub fn new(pty_pair: PtyPair, child: Pid) -> Result<HeadlessTerm, Box<dyn Error>> {
+        let master = pty_pair.master.as_raw_fd();
+        fcntl::fcntl(master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        let winsize = term::get_winsize(master)?;
+
+        let poll = Poll::new()?;
+        poll.registry()
+            .register(&mut SourceFd(&master), PTY_MASTER, Interest::READABLE)?;
+
+        Ok(HeadlessTerm {
+            pty_pair,
+            child,
+            poll,
+            events: Events::with_capacity(16),
+            screen: Screen::new(winsize.ws_row, winsize.ws_col),
+            eof: false,
+            pending: false,
+        })
+    }
+
+    /// The emulated screen, as of the output read so far.
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    /// Whether the program has closed the terminal.
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    fn master(&self) -> RawFd {
+        self.pty_pair.master.as_raw_fd()
+    }
+
+    /// Sends `data` to the program, as if it was typed.
+    pub fn send(&mut self, mut data: &[u8]) -> Result<(), Box<dyn Error>> {
+        while !data.is_empty() {
+            match unistd::write(self.master(), data) {
+                Ok(n) => data = &data[n..],
+                Err(nix::Error::Sys(Errno::EINTR)) => {}
+                // The program isn't reading its input. Keep reading its
+                // output in the meantime, in case it is waiting for that.
+                Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                    self.update(Some(Duration::from_millis(10)))?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Resizes the PTY and the screen.
+    pub fn resize(&mut self, winsize: &Winsize) -> Result<(), Box<dyn Error>> {
+        self.pty_pair.resize(winsize)?;
+        self.screen.resize(winsize.ws_row, winsize.ws_col);
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for output, and updates the screen with
+    /// everything available. Waits forever if `timeout` is `None`.
+    /// Returns whether there was any output.
+    pub fn update(&mut self, timeout: Option<Duration>) -> Result<bool, Box<dyn Error>> {
+        if self.eof {
+            return Ok(false);
+        }
+        if !self.pending {
+            match self.poll.poll(&mut self.events, timeout) {
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => return Ok(false),
+                res => res?,
+            }
+        }
+
+        let mut buf = [0u8; 4096];
+        let mut updated = false;
+        let mut read = 0;
+        self.pending = false;
+        loop {
+            if read >= MAX_UPDATE {
+                self.pending = true;
+                break;
+            }
+            let n = match unistd::read(self.master(), &mut buf) {
+                // Everything on the slave side is gone.
+                Ok(0) | Err(nix::Error::Sys(Errno::EIO)) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => n,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+                Err(err) => return Err(err.into()),
+            };
+            self.screen.process(&buf[..n]);
+            read += n;
+            updated = true;
+        }
+
+        // Answer what the program asked the terminal, such as where the
+        // cursor is.
+        let responses = self.screen.take_responses();
+        if !responses.is_empty() && !self.eof {
+            self.send(&responses)?;
+        }
+        Ok(updated)
+    }
+
+    /// Waits until `done` returns true for the screen.
+    /// Waits forever if `timeout` is `None`.
+    pub fn wait_for(
+        &mut self,
+        mut done: impl FnMut(&Screen) -> bool,
+        timeout: Option<Duration>,
+    ) -> Result<(), Box<dyn Error>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if done(&self.screen) {
+                return Ok(());
+            }
+            if self.eof {
+                return Err(ExpectError::Eof.into());
+            }
+            self.update(remaining(deadline)?)?;
+        }
+    }
+
+    /// Waits until the screen hasn't changed for `quiet`, or the program
+    /// closed the terminal.
+    /// Waits forever if `timeout` is `None`.
+    pub fn wait_stable(
+        &mut self,
+        quiet: Duration,
+        timeout: Option<Duration>,
+    ) -> Result<(), Box<dyn Error>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut changed = Instant::now();
+        while !self.eof {
+            let quiet_left = quiet.saturating_sub(changed.elapsed());
+            if quiet_left == Duration::from_secs(0) {
+                break;
+            }
+            let wait = match remaining(deadline)? {
+                Some(left) => quiet_left.min(left),
+                None => quiet_left,
+            };
+            if self.update(Some(wait))? {
+                changed = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the terminal, which hangs up the program unless it already
+    /// exited, and returns its exit status. A program that doesn't exit
+    /// when hung up is killed, which is returned as an error.
+    pub fn close(self) -> Result<i32, Box<dyn Error>> {
+        let HeadlessTerm {
+            pty_pair, child, ..
+        } = self;
+        drop(pty_pair);
+        reap_hung_up(child)
+    }
+}
@@ -0,0 +1,388 @@
+//! A minimal JSON parser, enough to read asciicast files.
+
+use std::error::Error;
+use std::fmt;
+
+/// How deeply arrays and objects may be nested.
+const MAX_DEPTH: usize = 128;
+
+/// A parsed JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the member `key` if this is an object that has it.
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// An error encountered while parsing JSON, with the byte offset it occurred at.
+#[derive(Debug)]
+pub(crate) struct ParseError {
+    msg: &'static str,
+    pos: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid JSON at offset {}: {}", self.pos, self.msg)
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parses `s`, which must contain exactly one JSON value.
+pub(crate) fn parse(s: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        input: s.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.input.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    // Arrays and objects the parser is in.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &'static str) -> ParseError {
+        ParseError { msg, pos: self.pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &'static str) -> Result<(), ParseError> {
+        if self.input[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| Value::Null),
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.nested(Parser::array),
+            Some(b'{') => self.nested(Parser::object),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// Parses an array or object with `parse`, one level further down.
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Value, ParseError>,
+    ) -> Result<Value, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("too deeply nested"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(":")?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.pos;
+        while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') | Some(b'E')
+        | Some(b'0'..=b'9') = self.peek()
+        {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.input[start..self.pos])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Value::Number)
+            .ok_or(ParseError {
+                msg: "invalid number",
+                pos: start,
+            })
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1;
+        let mut s = Vec::new();
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(s).map_err(|_| self.error("invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.pos += 1;
+                    let mut buf = [0; 4];
+                    s.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(b) => {
+                    s.push(b);
+                    self.pos += 1;
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    /// Parses a `\u` escape starting at the `u`, including a following low
+    /// surrogate escape if the first one is a high surrogate.
+    /// Leaves `pos` on the last hex digit.
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        self.pos += 1;
+        let mut code = self.hex4()?;
+        self.pos += 3;
+        if (0xd800..0xdc00).contains(&code) && self.input[self.pos + 1..].starts_with(b"\\u") {
+            self.pos += 3;
+            let low = self.hex4()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                // Not a pair after all, the second escape stands on its own.
+                self.pos -= 3;
+                return Ok('\u{fffd}');
+            }
+            self.pos += 3;
+            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+        }
+        Ok(std::char::from_u32(code).unwrap_or('\u{fffd}'))
+    }
+
+    fn hex4(&self) -> Result<u32, ParseError> {
+        self.input
+            .get(self.pos..self.pos + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(parse("null").unwrap(), Value::Null);
+        assert_eq!(parse(" true ").unwrap(), Value::Bool(true));
+        assert_eq!(parse("false").unwrap(), Value::Bool(false));
+        assert!(parse("nul").is_err());
+        assert!(parse("truex").is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        for (input, expected) in &[
+            ("0", 0.0),
+            ("-1", -1.0),
+            ("3.25", 3.25),
+            ("1e3", 1000.0),
+            ("-2.5E-1", -0.25),
+            ("0.000182", 0.000182),
+        ] {
+            assert_eq!(parse(input).unwrap(), Value::Number(*expected), "{}", input);
+        }
+        for input in &["-", "1.2.3", "1e", "--1", "+1", ".5"] {
+            assert!(parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(parse(r#""plain""#).unwrap(), string("plain"));
+        assert_eq!(
+            parse(r#""\" \\ \/ \b \f \n \r \t""#).unwrap(),
+            string("\" \\ / \u{8} \u{c} \n \r \t")
+        );
+        assert_eq!(parse(r#""\u001b[0m""#).unwrap(), string("\u{1b}[0m"));
+        assert_eq!(parse(r#""\u00e9\u20AC""#).unwrap(), string("é€"));
+        assert_eq!(parse("\"héllo\"").unwrap(), string("héllo"));
+        assert!(parse(r#""\x""#).is_err());
+        assert!(parse(r#""\u12""#).is_err());
+        assert!(parse(r#""\u+041""#).is_err());
+        assert!(parse(r#""\u 041""#).is_err());
+        assert!(parse(r#""unterminated"#).is_err());
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(parse(r#""\ud83d\ude00""#).unwrap(), string("😀"));
+        assert_eq!(parse(r#""a\uD834\uDD1Eb""#).unwrap(), string("a𝄞b"));
+        // Unpaired surrogates can't be decoded.
+        assert_eq!(parse(r#""\ud83d""#).unwrap(), string("\u{fffd}"));
+        assert_eq!(parse(r#""\ude00x""#).unwrap(), string("\u{fffd}x"));
+        assert_eq!(parse(r#""\ud83d\u0041""#).unwrap(), string("\u{fffd}A"));
+    }
+
+    #[test]
+    fn arrays_and_objects() {
+        let value =
+            parse(r#"{"version": 2, "env": {"TERM": "xterm"}, "e": [1, "o", []]}"#).unwrap();
+        assert_eq!(value.get("version").and_then(Value::as_f64), Some(2.0));
+        assert_eq!(
+            value
+                .get("env")
+                .and_then(|env| env.get("TERM"))
+                .and_then(Value::as_str),
+            Some("xterm")
+        );
+        assert_eq!(
+            value.get("e").and_then(Value::as_array),
+            Some(&[Value::Number(1.0), string("o"), Value::Array(Vec::new())][..])
+        );
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(parse("{}").unwrap(), Value::Object(Vec::new()));
+    }
+
+    #[test]
+    fn malformed() {
+        for input in &[
+            "",
+            "[",
+            "[1,",
+            "[1 2]",
+            "{\"a\"}",
+            "{\"a\": 1,}",
+            "{a: 1}",
+            "[1] x",
+            "\u{1}",
+        ] {
+            assert!(parse(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(parse(&nested(MAX_DEPTH + 1)).is_err());
+        let objects = "{\"a\":".repeat(MAX_DEPTH + 1) + "1" + &"}".repeat(MAX_DEPTH + 1);
+        assert!(parse(&objects).is_err());
+        // Deep enough to overflow the stack without a limit.
+        let err = parse(&"[".repeat(100_000)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid JSON at offset 128: too deeply nested"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! ptyme creates PTY pairs, runs programs on them and proxies between them
+//! and the terminal of the current process.
+//!
+//! ```no_run
+//! use ptyme::{signal, term::RawTerm, Filters, ProxyOptions, PtyPair};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let signals = signal::signal_pipe(&[nix::sys::signal::Signal::SIGWINCH])?;
+//! let pty_pair = PtyPair::open()?;
+//! let child = pty_pair.spawn(&["ls".to_string()])?;
+//!
+//! let _raw = RawTerm::new(0)?;
+//! let status = pty_pair.proxy(0, signals, Some(child), &mut Filters::new(), &ProxyOptions::default())?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod asciicast;
+pub mod audit;
+pub mod expect;
+pub mod filter;
+mod font;
+mod gif;
+pub mod headless;
+mod json;
+pub mod plain;
+pub mod play;
+mod png;
+mod proxy;
+mod pty;
+pub mod regex;
+pub mod render;
+pub mod screen;
+pub mod script;
+pub mod session;
+pub mod signal;
+pub mod snapshot;
+pub mod term;
+pub mod typescript;
+pub mod vt;
+
+pub use crate::filter::{Filter, Filters};
+pub use crate::proxy::ProxyOptions;
+pub use crate::pty::{wait_child, wait_status, ExitStatus, PtyPair};
@@ -1,128 +1,750 @@
+use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
 
-use nix::fcntl::OFlag;
+use nix::sys::signal::Signal;
 use nix::sys::termios;
-use nix::{pty, unistd};
+use nix::unistd;
 
-use mio::unix::SourceFd;
-use mio::{Events, Interest, Poll, Token};
-use nix::pty::PtyMaster;
+use ptyme::asciicast::{Cast, Recorder};
+use ptyme::audit::InputLog;
+use ptyme::expect::{Expect, ExpectError};
+use ptyme::headless::HeadlessTerm;
+use ptyme::plain::PlainLog;
+use ptyme::play::{self, PlayOptions};
+use ptyme::render::{self, GifOptions, Image};
+use ptyme::screen::Screen;
+use ptyme::script::Script;
+use ptyme::session::{self, InputMode, SessionOptions};
+use ptyme::signal::{self, FORWARDED_SIGNALS, TERMINATION_SIGNALS};
+use ptyme::term::{self, RawTerm, TermSettings, Winsize};
+use ptyme::typescript::Typescript;
+use ptyme::{ExitStatus, Filters, ProxyOptions, PtyPair};
 
-const STDIN: Token = Token(0);
-const PTY_MASTER: Token = Token(1);
+const USAGE: &str = "\
+usage: ptyme [--record <file> [--record-input]] [--log <file> [--timing <file>]]
+             [--log-input <file> [--mask-no-echo]] [--log-plain <file>]
+             [--json-status] [--kill-grace <secs>]
+             [--no-inherit-termios] [--stty <settings>] [-- <cmd> [args...]]
+       ptyme play [--speed <x>] [--idle-limit <secs>] [--step] <file>
+       ptyme new -s <name> [--input all|driver] [--scrollback <bytes>]
+             [--no-inherit-termios] [--stty <settings>] [--] <cmd> [args...]
+       ptyme attach [--read-only] [--detach-key <key>] <name>
+       ptyme run [--stty <settings>] <script> [--] <cmd> [args...]
+       ptyme screenshot [--svg <file>] [--png <file>] [--scale <n>] [--at <secs>]
+             [--timeout <secs>] [--size <cols>x<rows>]
+             (<recording> | -- <cmd> [args...])
+       ptyme gif [--fps <n>] [--idle-limit <secs>] [--scale <n>] <recording> <gif>";
 
-/// A PTY master / slave pair.
-struct PtyPair {
-    master: pty::PtyMaster,
-    slave_name: String,
+/// Size of PTYs when there is no terminal to take the size from, and
+/// `$COLUMNS` and `$LINES` don't say otherwise.
+const DEFAULT_WINSIZE: Winsize = Winsize {
+    ws_row: 24,
+    ws_col: 80,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+/// Largest number of seconds options accept, so that any deadline they
+/// set can be represented.
+const MAX_SECS: f64 = 1e9;
+
+/// How long the screen of a command has to stay the same before a
+/// screenshot of it is taken.
+const SCREENSHOT_QUIET: Duration = Duration::from_millis(200);
+
+/// How long to wait for the screen of a command to stop changing, before
+/// taking a screenshot of it anyway.
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Key that detaches from a session unless another one is given, Ctrl-\.
+const DEFAULT_DETACH_KEY: u8 = 0x1c;
+
+/// Command line options.
+#[derive(Default)]
+struct Options {
+    /// File to record the session to, in asciicast v2 format.
+    record: Option<PathBuf>,
+    /// Whether to record input as well as output.
+    record_input: bool,
+    /// File to log the output to, in the typescript format of `script`.
+    log: Option<PathBuf>,
+    /// File to write the timing of the output to, for `scriptreplay`.
+    timing: Option<PathBuf>,
+    /// File to log the output to as plain text, without escape sequences.
+    log_plain: Option<PathBuf>,
+    /// File to log the input to, for auditing.
+    log_input: Option<PathBuf>,
+    /// Whether to mask input typed while the command has echo turned off.
+    mask_no_echo: bool,
+    /// Whether to print how the command terminated to stderr, as JSON.
+    json_status: bool,
+    /// How signals are passed on to the command.
+    proxy: ProxyOptions,
+    /// Whether to leave the terminal settings of the PTY at their defaults,
+    /// rather than copying those of our terminal.
+    no_inherit_termios: bool,
+    /// Terminal settings for the command.
+    settings: TermSettings,
+    /// Command to run on the PTY slave.
+    cmd: Vec<String>,
 }
 
-/// Configures the given term to be in 'raw' mode.
-fn term_set_raw(fd: RawFd, termios: &mut termios::Termios) -> Result<(), nix::Error> {
-    termios::cfmakeraw(termios);
-    termios::tcsetattr(fd, termios::SetArg::TCSANOW, termios)
+/// Returns the size for PTYs when there is no terminal to take the size from.
+fn default_winsize() -> Winsize {
+    let var = |name| env::var(name).ok().and_then(|val| val.parse().ok());
+    Winsize {
+        ws_col: var("COLUMNS").unwrap_or(DEFAULT_WINSIZE.ws_col),
+        ws_row: var("LINES").unwrap_or(DEFAULT_WINSIZE.ws_row),
+        ..DEFAULT_WINSIZE
+    }
 }
 
-/// Writes the buffer `rdr` to the writer `f`.
-/// Always calls `f.flush()`.
-fn write_buffer_to(mut rdr: impl BufRead, mut f: impl Write) -> Result<(), Box<dyn Error>> {
-    let buf = rdr.fill_buf()?;
-    f.write_all(buf)?;
-    f.flush()?;
-    let len = buf.len();
-    rdr.consume(len);
+/// Parses the command line arguments, excluding the program name.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options::default();
 
-    Ok(())
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => {
+                let path = args.next().ok_or("--record requires a file")?;
+                opts.record = Some(path.into());
+            }
+            "--record-input" => opts.record_input = true,
+            "--log" => {
+                let path = args.next().ok_or("--log requires a file")?;
+                opts.log = Some(path.into());
+            }
+            "--log-plain" => {
+                let path = args.next().ok_or("--log-plain requires a file")?;
+                opts.log_plain = Some(path.into());
+            }
+            "--log-input" => {
+                let path = args.next().ok_or("--log-input requires a file")?;
+                opts.log_input = Some(path.into());
+            }
+            "--mask-no-echo" => opts.mask_no_echo = true,
+            "--timing" => {
+                let path = args.next().ok_or("--timing requires a file")?;
+                opts.timing = Some(path.into());
+            }
+            "--json-status" => opts.json_status = true,
+            "--no-inherit-termios" => opts.no_inherit_termios = true,
+            "--stty" => opts.settings = args.next().unwrap_or_default().parse()?,
+            "--kill-grace" => {
+                opts.proxy.kill_grace = Duration::from_secs_f64(parse_secs(&arg, args.next())?)
+            }
+            // Everything after `--` is the command to run on the PTY slave.
+            "--" => {
+                opts.cmd = args.collect();
+                break;
+            }
+            arg => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+
+    if opts.record_input && opts.record.is_none() {
+        return Err("--record-input requires --record".to_string());
+    }
+    if opts.timing.is_some() && opts.log.is_none() {
+        return Err("--timing requires --log".to_string());
+    }
+    if opts.mask_no_echo && opts.log_input.is_none() {
+        return Err("--mask-no-echo requires --log-input".to_string());
+    }
+
+    Ok(opts)
 }
 
-/// Proxies between stdin of this process to the master terminal device.
-fn proxy_term(stdin: RawFd, pty_master: PtyMaster) -> Result<(), Box<dyn Error>> {
-    let mut poll = Poll::new()?;
-    let mut events = Events::with_capacity(128);
-    let pty_master_fd = unistd::dup(pty_master.as_raw_fd())?;
-    let fpty_master: File = unsafe { File::from_raw_fd(pty_master_fd) };
-    let mut fpty_master = BufReader::new(fpty_master);
-
-    // Register stdin, wait for it to be readable.
-    poll.registry()
-        .register(&mut SourceFd(&stdin), STDIN, Interest::READABLE)?;
-
-    // Register PTY master, wait for it to be readable.
-    poll.registry().register(
-        &mut SourceFd(&pty_master_fd),
-        PTY_MASTER,
-        Interest::READABLE,
-    )?;
-
-    // Grab handle and lock stdin to prevent excess locking during
-    // our loop below.
-    let stdin = io::stdin();
-    let mut stdin_hdl = stdin.lock();
-    let stdout = io::stdout();
-    let mut stdout_hdl = stdout.lock();
-
-    loop {
-        // Poll for events, blocking until we get an event.
-        poll.poll(&mut events, None)?;
-
-        // Process each event.
-        for event in events.iter() {
-            if event.is_read_closed() {
-                return Ok(());
-            }
-            match event.token() {
-                STDIN => {
-                    write_buffer_to(&mut stdin_hdl, fpty_master.get_mut())?;
+/// Parses the arguments of the `play` subcommand.
+fn parse_play_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(PathBuf, PlayOptions), String> {
+    let mut opts = PlayOptions::default();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--speed" => {
+                opts.speed = parse_secs(&arg, args.next())?;
+                if !(play::MIN_SPEED..=play::MAX_SPEED).contains(&opts.speed) {
+                    return Err(format!(
+                        "--speed must be between {} and {}",
+                        play::MIN_SPEED,
+                        play::MAX_SPEED
+                    ));
                 }
-                PTY_MASTER => {
-                    write_buffer_to(&mut fpty_master, &mut stdout_hdl)?;
+            }
+            "--idle-limit" => opts.idle_limit = Some(parse_secs(&arg, args.next())?),
+            "--step" => opts.step = true,
+            arg if arg.starts_with("--") => return Err(format!("unknown argument: {}", arg)),
+            arg if path.is_none() => path = Some(PathBuf::from(arg)),
+            arg => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok((path.ok_or("play requires a file")?, opts))
+}
+
+/// Options of the `new` subcommand.
+struct NewOptions {
+    name: String,
+    input_mode: InputMode,
+    scrollback: usize,
+    no_inherit_termios: bool,
+    settings: TermSettings,
+    cmd: Vec<String>,
+}
+
+/// Parses the arguments of the `new` subcommand.
+fn parse_new_args(mut args: impl Iterator<Item = String>) -> Result<NewOptions, String> {
+    let mut name = None;
+    let mut input_mode = InputMode::All;
+    let mut scrollback = session::DEFAULT_SCROLLBACK;
+    let mut no_inherit_termios = false;
+    let mut settings = TermSettings::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-s" | "--session" => name = Some(args.next().ok_or("-s requires a name")?),
+            "--input" => input_mode = args.next().unwrap_or_default().parse()?,
+            "--scrollback" => {
+                scrollback = args
+                    .next()
+                    .and_then(|bytes| bytes.parse().ok())
+                    .ok_or("--scrollback requires a number of bytes")?;
+                if scrollback > session::MAX_SCROLLBACK {
+                    return Err(format!(
+                        "--scrollback can't be more than {} bytes",
+                        session::MAX_SCROLLBACK
+                    ));
+                }
+            }
+            "--no-inherit-termios" => no_inherit_termios = true,
+            "--stty" => settings = args.next().unwrap_or_default().parse()?,
+            "--" => break,
+            arg if arg.starts_with('-') => return Err(format!("unknown argument: {}", arg)),
+            arg => {
+                let mut cmd = vec![arg.to_string()];
+                cmd.extend(args);
+                return Ok(NewOptions {
+                    name: name.ok_or("new requires -s <name>")?,
+                    input_mode,
+                    scrollback,
+                    no_inherit_termios,
+                    settings,
+                    cmd,
+                });
+            }
+        }
+    }
+
+    let cmd: Vec<String> = args.collect();
+    if cmd.is_empty() {
+        return Err("new requires a command".to_string());
+    }
+    Ok(NewOptions {
+        name: name.ok_or("new requires -s <name>")?,
+        input_mode,
+        scrollback,
+        no_inherit_termios,
+        settings,
+        cmd,
+    })
+}
+
+/// Options of the `attach` subcommand.
+struct AttachOptions {
+    name: String,
+    detach_key: u8,
+    read_only: bool,
+}
+
+/// Parses the arguments of the `attach` subcommand.
+fn parse_attach_args(mut args: impl Iterator<Item = String>) -> Result<AttachOptions, String> {
+    let mut name = None;
+    let mut detach_key = DEFAULT_DETACH_KEY;
+    let mut read_only = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--detach-key" => {
+                let key = args.next().unwrap_or_default();
+                detach_key = term::parse_char(&key)
+                    .ok_or_else(|| format!("invalid detach key: {:?}", key))?;
+            }
+            "-r" | "--read-only" => read_only = true,
+            arg if arg.starts_with('-') => return Err(format!("unknown argument: {}", arg)),
+            arg if name.is_none() => name = Some(arg.to_string()),
+            arg => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    Ok(AttachOptions {
+        name: name.ok_or("attach requires a session name")?,
+        detach_key,
+        read_only,
+    })
+}
+
+/// Options of the `run` subcommand.
+struct RunOptions {
+    script: PathBuf,
+    settings: TermSettings,
+    cmd: Vec<String>,
+}
+
+/// Parses the arguments of the `run` subcommand into the script, and the
+/// command to run it against.
+fn parse_run_args(mut args: impl Iterator<Item = String>) -> Result<RunOptions, String> {
+    let mut settings = TermSettings::default();
+
+    let script = loop {
+        match args.next() {
+            Some(ref arg) if arg == "--stty" => {
+                settings = args.next().unwrap_or_default().parse()?
+            }
+            Some(ref arg) if arg.starts_with("--") => {
+                return Err(format!("unknown argument: {}", arg))
+            }
+            Some(script) => break script,
+            None => return Err("run requires a script".to_string()),
+        }
+    };
+
+    let mut cmd: Vec<String> = args.collect();
+    if cmd.first().map(String::as_str) == Some("--") {
+        cmd.remove(0);
+    }
+    if cmd.is_empty() {
+        return Err("run requires a command".to_string());
+    }
+    Ok(RunOptions {
+        script: script.into(),
+        settings,
+        cmd,
+    })
+}
+
+/// Options of the `screenshot` subcommand.
+struct ScreenshotOptions {
+    svg: Option<PathBuf>,
+    png: Option<PathBuf>,
+    /// How many pixels of the PNG image each pixel of the font takes up,
+    /// across and down.
+    scale: usize,
+    /// Seconds into the recording or the run of the command to take the
+    /// screenshot at, rather than at the end.
+    at: Option<f64>,
+    /// Longest to wait for the screen of the command to stop changing.
+    timeout: Duration,
+    size: Option<Winsize>,
+    recording: Option<PathBuf>,
+    cmd: Vec<String>,
+}
+
+/// Parses the arguments of the `screenshot` subcommand.
+fn parse_screenshot_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<ScreenshotOptions, String> {
+    let mut opts = ScreenshotOptions {
+        svg: None,
+        png: None,
+        scale: 2,
+        at: None,
+        timeout: SCREENSHOT_TIMEOUT,
+        size: None,
+        recording: None,
+        cmd: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--svg" => opts.svg = Some(args.next().ok_or("--svg requires a file")?.into()),
+            "--png" => opts.png = Some(args.next().ok_or("--png requires a file")?.into()),
+            "--scale" => opts.scale = parse_scale(args.next())?,
+            "--at" => opts.at = Some(parse_secs(&arg, args.next())?),
+            "--timeout" => opts.timeout = Duration::from_secs_f64(parse_secs(&arg, args.next())?),
+            "--size" => opts.size = Some(parse_size(args.next())?),
+            "--" => {
+                opts.cmd = args.collect();
+                break;
+            }
+            arg if arg.starts_with("--") => return Err(format!("unknown argument: {}", arg)),
+            arg if opts.recording.is_none() => opts.recording = Some(PathBuf::from(arg)),
+            arg => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+
+    if opts.svg.is_none() && opts.png.is_none() {
+        return Err("screenshot requires --svg or --png".to_string());
+    }
+    match (&opts.recording, opts.cmd.is_empty()) {
+        (None, true) => Err("screenshot requires a recording or a command".to_string()),
+        (Some(_), false) => Err("screenshot takes a recording or a command, not both".to_string()),
+        _ => Ok(opts),
+    }
+}
+
+/// Parses the arguments of the `gif` subcommand into the recording, the GIF
+/// to write and the options.
+fn parse_gif_args(
+    mut args: impl Iterator<Item = String>,
+) -> Result<(PathBuf, PathBuf, GifOptions), String> {
+    let mut opts = GifOptions::default();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fps" => {
+                opts.fps = parse_secs(&arg, args.next())?;
+                if opts.fps <= 0.0 {
+                    return Err("--fps must be positive".to_string());
                 }
-                // We don't expect any events with tokens other than those we provided.
-                _ => unreachable!(),
             }
+            "--idle-limit" => opts.idle_limit = Some(parse_secs(&arg, args.next())?),
+            "--scale" => opts.scale = parse_scale(args.next())?,
+            arg if arg.starts_with("--") => return Err(format!("unknown argument: {}", arg)),
+            arg if paths.len() < 2 => paths.push(PathBuf::from(arg)),
+            arg => return Err(format!("unexpected argument: {}", arg)),
         }
     }
+
+    let gif = paths
+        .pop()
+        .ok_or("gif requires a recording and a file to write")?;
+    let cast = paths.pop().ok_or("gif requires a file to write")?;
+    Ok((cast, gif, opts))
 }
 
-/// Opens and returns a new PTY pair.
-/// The pair contains the PTY master FD and the slave path.
-fn new_pty() -> Result<PtyPair, Box<dyn Error>> {
-    // Open a new PTY master.
-    let master = pty::posix_openpt(OFlag::O_RDWR)?;
+/// Parses the scale of images, from 1 to `render::MAX_SCALE`.
+fn parse_scale(value: Option<String>) -> Result<usize, String> {
+    value
+        .and_then(|scale| scale.parse().ok())
+        .filter(|scale| (1..=render::MAX_SCALE).contains(scale))
+        .ok_or_else(|| format!("--scale requires a number from 1 to {}", render::MAX_SCALE))
+}
 
-    // Allow a slave to be generated for it.
-    pty::grantpt(&master)?;
-    pty::unlockpt(&master)?;
+/// Parses a terminal size given as `<cols>x<rows>`.
+fn parse_size(value: Option<String>) -> Result<Winsize, String> {
+    value
+        .as_ref()
+        .and_then(|value| value.split_once('x'))
+        .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)))
+        .filter(|&(cols, rows)| cols > 0 && rows > 0)
+        .map(|(cols, rows)| Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        })
+        .ok_or_else(|| "--size requires <cols>x<rows>".to_string())
+}
 
-    // Get the name of the slave.
-    let slave_name = unsafe { pty::ptsname(&master) }?;
+/// Parses the non-negative number of seconds (or factor) given for `option`,
+/// which must be at most `MAX_SECS`.
+fn parse_secs(option: &str, value: Option<String>) -> Result<f64, String> {
+    value
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|value| (0.0..=MAX_SECS).contains(value))
+        .ok_or_else(|| format!("{} requires a number from 0 to {}", option, MAX_SECS))
+}
 
-    Ok(PtyPair { master, slave_name })
+/// Exits with a usage message if parsing the command line failed.
+fn or_usage<T>(parsed: Result<T, String>) -> T {
+    parsed.unwrap_or_else(|err| {
+        eprintln!("ptyme: {}\n{}", err, USAGE);
+        process::exit(2);
+    })
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1).peekable();
+
+    let status = match args.peek().map(String::as_str) {
+        Some("play") => {
+            args.next();
+            let (path, opts) = or_usage(parse_play_args(args));
+            play_cast(&path, &opts)?
+        }
+        Some("new") => {
+            args.next();
+            new_session(&or_usage(parse_new_args(args)))?
+        }
+        Some("attach") => {
+            args.next();
+            attach_session(&or_usage(parse_attach_args(args)))?
+        }
+        Some("run") => {
+            args.next();
+            run_script(&or_usage(parse_run_args(args)))?
+        }
+        Some("screenshot") => {
+            args.next();
+            screenshot(&or_usage(parse_screenshot_args(args)))?
+        }
+        Some("gif") => {
+            args.next();
+            let (cast, gif, opts) = or_usage(parse_gif_args(args));
+            let cast = Cast::open(cast)?;
+            let file = BufWriter::new(File::create(gif)?);
+            render::write_gif(&cast, file, &opts)?;
+            0
+        }
+        _ => run(&or_usage(parse_args(args)))?,
+    };
+
+    if status != 0 {
+        process::exit(status);
+    }
+
+    Ok(())
+}
+
+/// Starts a session running in the background.
+fn new_session(opts: &NewOptions) -> Result<i32, Box<dyn Error>> {
+    let stdin: RawFd = 0;
+
+    // Size the session after our terminal, if we have one.
+    let winsize = term::get_winsize(stdin).unwrap_or_else(|_| default_winsize());
+    // Let the session start out with the settings of our terminal, too.
+    let termios = if unistd::isatty(stdin)? && !opts.no_inherit_termios {
+        Some(termios::tcgetattr(stdin)?)
+    } else {
+        None
+    };
+    let session_opts = SessionOptions {
+        winsize,
+        input_mode: opts.input_mode,
+        scrollback: opts.scrollback,
+        termios,
+        settings: opts.settings.clone(),
+    };
+    session::create(&opts.name, &opts.cmd, &session_opts)?;
+
+    Ok(0)
+}
+
+/// Attaches our terminal to a session, until it ends or the detach key
+/// is typed.
+fn attach_session(opts: &AttachOptions) -> Result<i32, Box<dyn Error>> {
+    let stdin: RawFd = 0;
+
+    let mut handled = vec![Signal::SIGWINCH];
+    handled.extend_from_slice(&TERMINATION_SIGNALS);
+    let signals = signal::signal_pipe(&handled)?;
+
+    let stream = session::connect(&opts.name)?;
+    let _raw = RawTerm::new(stdin)?;
+    session::attach(stream, stdin, signals, opts.detach_key, opts.read_only)
+}
+
+/// Runs the command given in `opts` on a new PTY, driven by the script.
+/// Returns the exit status of the command if the script waited for it to
+/// finish, otherwise 0, or 1 if the script failed.
+fn run_script(opts: &RunOptions) -> Result<i32, Box<dyn Error>> {
+    let stdin: RawFd = 0;
+    let path = &opts.script;
+    let script = Script::open(path)?;
+
+    let pty_pair = PtyPair::open()?;
+    pty_pair.resize(&term::get_winsize(stdin).unwrap_or_else(|_| default_winsize()))?;
+    pty_pair.configure(&opts.settings)?;
+    let child = pty_pair.spawn(&opts.cmd)?;
+    let mut exp = Expect::new(pty_pair, child)?;
+    exp.set_log(Some(Box::new(io::stdout())));
+
+    if let Err(err) = script.run(&mut exp) {
+        eprintln!("\nptyme: {}: {}", path.display(), err);
+        exp.close()?;
+        return Ok(1);
+    }
+
+    let eof = exp.is_eof();
+    let status = exp.close()?;
+    Ok(if eof { status } else { 0 })
+}
+
+/// Writes a screenshot of a recorded session, or of a command run on a
+/// headless terminal, as SVG and PNG images.
+fn screenshot(opts: &ScreenshotOptions) -> Result<i32, Box<dyn Error>> {
+    let size = opts.size.unwrap_or_else(default_winsize);
+    if opts.png.is_some() && opts.recording.is_none() {
+        // Rather than find out after running the command.
+        render::image_size(size.ws_row, size.ws_col, opts.scale)?;
+    }
+
+    let screen = match opts.recording {
+        Some(ref path) => {
+            let mut screen = Cast::open(path)?.screen_at(opts.at);
+            if let Some(ref size) = opts.size {
+                screen.resize(size.ws_row, size.ws_col);
+            }
+            screen
+        }
+        None => capture_command(&opts.cmd, &size, opts.at, opts.timeout)?,
+    };
+
+    if let Some(ref path) = opts.svg {
+        fs::write(path, render::svg(&screen))?;
+    }
+    if let Some(ref path) = opts.png {
+        let image = Image::from_screen(&screen, opts.scale)?;
+        image.write_png(BufWriter::new(File::create(path)?))?;
+    }
+    Ok(0)
+}
+
+/// Runs `cmd` on a headless terminal of size `winsize`, and returns its
+/// screen `at` seconds in, or once it stopped changing, the command exited
+/// or `timeout` passed. The command is hung up afterwards, unless it exited.
+fn capture_command(
+    cmd: &[String],
+    winsize: &Winsize,
+    at: Option<f64>,
+    timeout: Duration,
+) -> Result<Screen, Box<dyn Error>> {
+    let mut term = HeadlessTerm::spawn(cmd, winsize)?;
+    match at {
+        Some(at) => {
+            let deadline = Instant::now() + Duration::from_secs_f64(at);
+            while !term.is_eof() {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                term.update(Some(deadline - now))?;
+            }
+        }
+        None => match term.wait_stable(SCREENSHOT_QUIET, Some(timeout)) {
+            // Programs that keep redrawing get their picture taken as is.
+            Err(err) if err.downcast_ref() == Some(&ExpectError::Timeout) => {}
+            res => res?,
+        },
+    }
+    let screen = term.screen().clone();
+    // The screen is taken all the same, if the command had to be killed.
+    if let Err(err) = term.close() {
+        eprintln!("ptyme: {}", err);
+    }
+    Ok(screen)
+}
+
+/// Plays back the recorded session at `path`.
+fn play_cast(path: &Path, opts: &PlayOptions) -> Result<i32, Box<dyn Error>> {
     let stdin: RawFd = 0;
+    let cast = Cast::open(path)?;
+    let signals = signal::signal_pipe(&TERMINATION_SIGNALS)?;
 
-    // Get the termios config for the terminal connected to this process.
-    let saved = termios::tcgetattr(stdin)?;
-    let mut termios = saved.clone();
+    // Take over the terminal, if there is one, so keypresses arrive immediately.
+    let _raw = if unistd::isatty(stdin)? {
+        Some(RawTerm::new(stdin)?)
+    } else {
+        None
+    };
+
+    play::play(&cast, stdin, signals, opts)
+}
+
+/// Runs the command given in `opts` on a new PTY and proxies to it.
+/// Returns the exit status of the command, or 128 + the signal number if it
+/// was killed by a signal.
+fn run(opts: &Options) -> Result<i32, Box<dyn Error>> {
+    let stdin: RawFd = 0;
+
+    // Route resizes, and signals for the command, into the poll loop.
+    let mut handled = vec![Signal::SIGWINCH];
+    handled.extend_from_slice(&FORWARDED_SIGNALS);
+    let signals = signal::signal_pipe(&handled)?;
 
     // Open a new pty master device.
-    let pty_pair = new_pty()?;
+    let pty_pair = PtyPair::open()?;
 
-    println!("Opened new PTY device: {}", pty_pair.slave_name);
+    // Without a terminal on stdin, such as in CI, input is forwarded as it
+    // is and the program still gets a PTY to write to.
+    let interactive = unistd::isatty(stdin)?;
 
-    // Set the current terminal to 'raw' mode.
-    term_set_raw(stdin, &mut termios)?;
+    // Give the PTY the same size as our terminal before anything runs on it.
+    if interactive {
+        term::copy_winsize(stdin, pty_pair.master.as_raw_fd())?;
+    } else {
+        pty_pair.resize(&default_winsize())?;
+    }
+    // Have the erase key, Ctrl-C and the like work as they do on our terminal,
+    // unless told otherwise.
+    if interactive && !opts.no_inherit_termios {
+        term::copy_termios(stdin, pty_pair.master.as_raw_fd())?;
+    }
+    pty_pair.configure(&opts.settings)?;
 
-    // Proxy between our stdin device and the PTY master device.
-    proxy_term(stdin, pty_pair.master)?;
+    let winsize = term::get_winsize(pty_pair.master.as_raw_fd())?;
+    let mut filters = Filters::new();
+    if let Some(ref path) = opts.record {
+        filters.push(Recorder::create(
+            path,
+            &winsize,
+            &opts.cmd,
+            opts.record_input,
+        )?);
+    }
+    if let Some(ref path) = opts.log {
+        filters.push(Typescript::create(
+            path,
+            opts.timing.as_deref(),
+            &winsize,
+            &opts.cmd,
+        )?);
+    }
+    if let Some(ref path) = opts.log_plain {
+        filters.push(PlainLog::create(path)?);
+    }
+    if let Some(ref path) = opts.log_input {
+        filters.push(InputLog::open(path, opts.mask_no_echo)?);
+    }
 
-    // Restore the terminal to its original settings.
-    termios::tcsetattr(stdin, termios::SetArg::TCSANOW, &saved)?;
+    let started = Instant::now();
+    let child = if opts.cmd.is_empty() {
+        println!("Opened new PTY device: {}", pty_pair.slave_name);
+        None
+    } else {
+        Some(pty_pair.spawn(&opts.cmd)?)
+    };
 
-    Ok(())
+    let status = {
+        // Set the current terminal to 'raw' mode, until the session ends.
+        let _raw = if interactive {
+            Some(RawTerm::new(stdin)?)
+        } else {
+            None
+        };
+
+        // Proxy between our stdin device and the PTY master device.
+        pty_pair.proxy(stdin, signals, child, &mut filters, &opts.proxy)?
+    };
+
+    if opts.json_status {
+        eprintln!("{}", json_status(&status, started.elapsed()));
+    }
+
+    Ok(status.code)
+}
+
+/// Formats how a command terminated, and how long it ran, as a JSON object.
+fn json_status(status: &ExitStatus, runtime: Duration) -> String {
+    let signal = match status.signal {
+        Some(sig) => format!("\"{}\"", sig),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"exit_code\": {}, \"signal\": {}, \"core_dumped\": {}, \"runtime\": {:.6}}}",
+        status.code,
+        signal,
+        status.core_dumped,
+        runtime.as_secs_f64()
+    )
 }
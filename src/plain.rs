@@ -0,0 +1,286 @@
+//! Rendering of terminal output as plain text, for logs that can be read and
+//! searched with ordinary tools.
+//!
+//! Escape sequences for colors, cursor movement, window titles and the like
+//! are dropped. Carriage returns, backspaces, tabs and the few cursor
+//! movements within a line are applied, so progress bars and corrected typos
+//! end up as they looked on the screen. Trailing whitespace is trimmed from
+//! every line.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+
+use crate::filter::{keep_error, Filter};
+use crate::pty::ExitStatus;
+use crate::vt::{Parser, Perform};
+
+/// Furthest column the cursor can be moved to, so that a cursor movement
+/// can't make a line arbitrarily long. Text printed beyond it is kept.
+const MAX_COLUMNS: usize = 4096;
+
+/// Turns a stream of terminal output into plain text, a line at a time.
+#[derive(Debug, Default)]
+pub struct PlainText {
+    parser: Parser,
+    line: Line,
+}
+
+/// The line being written, and the text of the lines completed so far.
+#[derive(Debug, Default)]
+struct Line {
+    chars: Vec<char>,
+    col: usize,
+    done: String,
+}
+
+impl PlainText {
+    /// Returns a renderer that hasn't seen any output yet.
+    pub fn new() -> PlainText {
+        PlainText::default()
+    }
+
+    /// Renders `data`, and returns the lines it completed, each ending in a
+    /// newline.
+    pub fn push(&mut self, data: &[u8]) -> String {
+        self.parser.advance(&mut self.line, data);
+        mem::take(&mut self.line.done)
+    }
+
+    /// Returns the text of the line that hasn't been completed yet, if any.
+    pub fn finish(mut self) -> String {
+        if !self.line.chars.is_empty() {
+            self.line.end();
+        }
+        self.line.done
+    }
+}
+
+impl Line {
+    /// Completes the current line.
+    fn end(&mut self) {
+        while self.chars.last().is_some_and(|c| c.is_whitespace()) {
+            self.chars.pop();
+        }
+        self.done.extend(self.chars.drain(..));
+        self.done.push('\n');
+        self.col = 0;
+    }
+
+    /// Moves the cursor to `col`, padding the line with spaces up to it.
+    fn pad_to(&mut self, col: usize) {
+        if self.chars.len() < col {
+            self.chars.resize(col, ' ');
+        }
+    }
+}
+
+impl Perform for Line {
+    fn print(&mut self, c: char) {
+        self.pad_to(self.col);
+        if self.col < self.chars.len() {
+            self.chars[self.col] = c;
+        } else {
+            self.chars.push(c);
+        }
+        self.col += 1;
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\r' => self.col = 0,
+            b'\x08' => self.col = self.col.saturating_sub(1),
+            b'\t' => self.col = (self.col / 8 + 1) * 8,
+            // Line feed, vertical tab and form feed.
+            b'\n' | b'\x0b' | b'\x0c' => self.end(),
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &[u16], intermediates: &[u8], action: u8) {
+        if !intermediates.is_empty() {
+            return;
+        }
+        let param = params.first().copied().unwrap_or(0) as usize;
+        let count = param.max(1);
+        match action {
+            // Cursor forward, and back.
+            b'C' => self.col = (self.col + count).min(self.col.max(MAX_COLUMNS)),
+            b'D' => self.col = self.col.saturating_sub(count),
+            // Cursor to column.
+            b'G' => self.col = count.min(MAX_COLUMNS) - 1,
+            // Erase in line: to the end, from the start, or all of it.
+            b'K' => match param {
+                0 => self.chars.truncate(self.col),
+                1 => {
+                    let end = (self.col + 1).min(self.chars.len());
+                    self.chars[..end].iter_mut().for_each(|c| *c = ' ');
+                }
+                2 => self.chars.clear(),
+                _ => {}
+            },
+            // Erase characters at the cursor.
+            b'X' => {
+                let end = (self.col + count).min(self.chars.len());
+                if self.col < end {
+                    self.chars[self.col..end].iter_mut().for_each(|c| *c = ' ');
+                }
+            }
+            // Delete characters at the cursor.
+            b'P' => {
+                let end = (self.col + count).min(self.chars.len());
+                if self.col < end {
+                    self.chars.drain(self.col..end);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes the output of a session to a file as plain text.
+pub struct PlainLog {
+    file: BufWriter<File>,
+    text: PlainText,
+    // The first error writing the file as a filter, for `take_error`.
+    error: Option<io::Error>,
+}
+
+impl PlainLog {
+    /// Creates the plain text log at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<PlainLog, Box<dyn Error>> {
+        Ok(PlainLog {
+            file: BufWriter::new(File::create(path)?),
+            text: PlainText::new(),
+            error: None,
+        })
+    }
+
+    /// Logs `data` written by the program to the terminal. Only complete
+    /// lines are written out.
+    pub fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        let text = self.text.push(data);
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all(text.as_bytes())?;
+        self.file.flush()
+    }
+
+    /// Writes out the last line, if it wasn't completed.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_last_line()
+    }
+
+    fn write_last_line(&mut self) -> io::Result<()> {
+        let text = mem::take(&mut self.text);
+        self.file.write_all(text.finish().as_bytes())?;
+        self.file.flush()
+    }
+}
+
+/// Logs the output as it passes through, without changing anything.
+impl Filter for PlainLog {
+    fn on_output(&mut self, data: &[u8]) -> Vec<u8> {
+        let res = self.output(data);
+        keep_error(&mut self.error, res);
+        data.to_vec()
+    }
+
+    fn on_exit(&mut self, _status: &ExitStatus) -> io::Result<()> {
+        self.write_last_line()
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(data: &[u8]) -> String {
+        let mut text = PlainText::new();
+        let mut out = text.push(data);
+        out.push_str(&text.finish());
+        out
+    }
+
+    const CASES: &[(&[u8], &str)] = &[
+        (b"hello\r\n", "hello\n"),
+        (b"no newline", "no newline\n"),
+        (b"", ""),
+        (b"a\n\nb\n", "a\n\nb\n"),
+        (b"trailing   \t\n", "trailing\n"),
+        // Carriage returns overwrite the line from its start.
+        (b"abc\rX\n", "Xbc\n"),
+        (b"progress 10%\rprogress 100%\n", "progress 100%\n"),
+        (b"a\rb\r\nc", "b\nc\n"),
+        // Backspaces move back, and stop at the start of the line.
+        (b"abc\x08\x08X\n", "aXc\n"),
+        (b"ab\x08\x08\x08\x08c\n", "cb\n"),
+        (b"typo\x08 \x08\x08o\n", "tyo\n"),
+        (b"a\tb\n", "a       b\n"),
+        (b"a\x0bb\x0cc", "a\nb\nc\n"),
+        // Escape sequences are dropped, and movements within the line applied.
+        (b"\x1b[1;31mred\x1b[0m \x1b]0;title\x07\n", "red\n"),
+        (b"abc\x1b[2DX\n", "aXc\n"),
+        (b"ab\x1b[3Cc\n", "ab   c\n"),
+        (b"a\x1b[5Gb\n", "a   b\n"),
+        (b"abcdef\x1b[3G\x1b[K\n", "ab\n"),
+        (b"abcdef\x1b[3G\x1b[1K\n", "   def\n"),
+        (b"abcdef\x1b[2K\rx\n", "x\n"),
+        (b"abcdef\x1b[2G\x1b[2X\n", "a  def\n"),
+        (b"abcdef\x1b[2G\x1b[2P\n", "adef\n"),
+        (b"a\x1b[65535G\x1b[65535Db\n", "b\n"),
+        (b"abc\x1b[?25l\x1b[2 q\n", "abc\n"),
+    ];
+
+    #[test]
+    fn cursor_stays_within_max_columns() {
+        let spaces = |n: usize| " ".repeat(n);
+        assert_eq!(render(b"\x1b[65535Gx"), spaces(MAX_COLUMNS - 1) + "x\n");
+        assert_eq!(
+            render(b"\x1b[4000Cab\x1b[4000Cc"),
+            spaces(4000) + "ab" + &spaces(MAX_COLUMNS - 4002) + "c\n"
+        );
+        // Text printed beyond the limit is kept, but the cursor isn't moved
+        // further forward.
+        let mut input = "x".repeat(MAX_COLUMNS + 10).into_bytes();
+        input.extend_from_slice(b"\x1b[5Cy\x1b[65535G\x1b[2Xz");
+        assert_eq!(
+            render(&input),
+            "x".repeat(MAX_COLUMNS - 1) + "z " + &"x".repeat(9) + "y\n"
+        );
+    }
+
+    #[test]
+    fn rendering() {
+        for (input, expected) in CASES {
+            assert_eq!(render(input), *expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn only_complete_lines_are_pushed() {
+        let mut text = PlainText::new();
+        assert_eq!(text.push(b"ab"), "");
+        assert_eq!(text.push(b"c\nd\x1b[1"), "abc\n");
+        assert_eq!(text.push(b"m\re\n\n"), "e\n\n");
+        assert_eq!(text.push(b"last"), "");
+        assert_eq!(text.finish(), "last\n");
+    }
+
+    #[test]
+    fn output_split_anywhere() {
+        for (input, expected) in CASES {
+            let mut text = PlainText::new();
+            let mut out: String = input.chunks(1).map(|byte| text.push(byte)).collect();
+            out.push_str(&text.finish());
+            assert_eq!(out, *expected, "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Playback of recorded sessions.
+
+use std::error::Error;
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use nix::unistd;
+
+use crate::asciicast::{Cast, Event};
+use crate::signal::read_signals;
+
+const STDIN: Token = Token(0);
+const SIGNAL: Token = Token(1);
+
+/// Pauses or resumes playback.
+const KEY_PAUSE: u8 = b' ';
+/// Writes the next event while paused.
+const KEY_STEP: u8 = b'.';
+/// Stops playback.
+const KEY_QUIT: u8 = b'q';
+const KEY_CTRL_C: u8 = 0x03;
+
+/// The slowest and fastest supported playback speeds.
+pub const MIN_SPEED: f64 = 0.01;
+pub const MAX_SPEED: f64 = 1000.0;
+/// Upper bound on the pause between two events, however far apart they are.
+const MAX_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Options controlling the playback of a session.
+#[derive(Debug, Clone)]
+pub struct PlayOptions {
+    /// Playback speed, relative to the original timing.
+    pub speed: f64,
+    /// Upper bound, in seconds, on the pause between two events.
+    pub idle_limit: Option<f64>,
+    /// Start paused, so the session can be advanced one event at a time.
+    pub step: bool,
+}
+
+impl Default for PlayOptions {
+    fn default() -> PlayOptions {
+        PlayOptions {
+            speed: 1.0,
+            idle_limit: None,
+            step: false,
+        }
+    }
+}
+
+impl PlayOptions {
+    /// Returns how long to wait before playing back `event`, given the time
+    /// of the event before it.
+    fn delay(&self, prev_time: f64, event: &Event) -> Duration {
+        let mut delay = (event.time - prev_time).max(0.0);
+        if let Some(limit) = self.idle_limit {
+            delay = delay.min(limit);
+        }
+        Duration::try_from_secs_f64(delay / self.speed)
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY))
+    }
+}
+
+/// Writes the output of `cast` to stdout with its original timing, adjusted
+/// by `opts`. Keys read from `stdin` control the playback: space pauses and
+/// resumes, '.' steps one event forward while paused and 'q' quits.
+///
+/// Returns 0 once playback is done, or 128 + the signal number if a
+/// termination signal arrives on the `signals` pipe first.
+pub fn play(
+    cast: &Cast,
+    stdin: RawFd,
+    signals: RawFd,
+    opts: &PlayOptions,
+) -> Result<i32, Box<dyn Error>> {
+    // Stepping waits for keypresses, which only a terminal delivers.
+    if opts.step && !unistd::isatty(stdin)? {
+        return Err("--step requires a terminal on stdin".into());
+    }
+
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(16);
+
+    // Only terminals deliver keypresses, anything else can't be polled
+    // or is better left alone.
+    let mut stdin_open = unistd::isatty(stdin)?;
+    if stdin_open {
+        poll.registry()
+            .register(&mut SourceFd(&stdin), STDIN, Interest::READABLE)?;
+    }
+    poll.registry()
+        .register(&mut SourceFd(&signals), SIGNAL, Interest::READABLE)?;
+
+    let stdout = io::stdout();
+    let mut stdout_hdl = stdout.lock();
+
+    let output: Vec<&Event> = cast.events.iter().filter(|e| e.code == "o").collect();
+    let mut next = 0;
+    let mut paused = opts.step;
+    // Time left until the next event is due, while paused.
+    let mut remaining = output
+        .first()
+        .map_or(Duration::from_secs(0), |e| opts.delay(0.0, e));
+    let mut deadline = Instant::now() + remaining;
+
+    while next < output.len() {
+        let timeout = if paused {
+            None
+        } else {
+            let now = Instant::now();
+            if deadline <= now {
+                None
+            } else {
+                Some(deadline - now)
+            }
+        };
+
+        // The next event is due.
+        if !paused && timeout.is_none() {
+            stdout_hdl.write_all(output[next].data.as_bytes())?;
+            stdout_hdl.flush()?;
+            let prev_time = output[next].time;
+            next += 1;
+            if let Some(event) = output.get(next) {
+                deadline += opts.delay(prev_time, event);
+            }
+            continue;
+        }
+
+        match poll.poll(&mut events, timeout) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        }
+
+        for event in events.iter() {
+            match event.token() {
+                STDIN => {
+                    let mut keys = [0u8; 64];
+                    let n = unistd::read(stdin, &mut keys)?;
+                    if n == 0 && stdin_open {
+                        poll.registry().deregister(&mut SourceFd(&stdin))?;
+                        stdin_open = false;
+                    }
+                    for &key in &keys[..n] {
+                        match key {
+                            KEY_PAUSE if paused => {
+                                paused = false;
+                                deadline = Instant::now() + remaining;
+                            }
+                            KEY_PAUSE => {
+                                paused = true;
+                                remaining = deadline.saturating_duration_since(Instant::now());
+                            }
+                            KEY_STEP if paused && next < output.len() => {
+                                stdout_hdl.write_all(output[next].data.as_bytes())?;
+                                stdout_hdl.flush()?;
+                                let prev_time = output[next].time;
+                                next += 1;
+                                if let Some(event) = output.get(next) {
+                                    remaining = opts.delay(prev_time, event);
+                                }
+                            }
+                            KEY_QUIT | KEY_CTRL_C => return Ok(0),
+                            _ => {}
+                        }
+                    }
+                }
+                SIGNAL => {
+                    if let Some(sig) = read_signals(signals)?.into_iter().next() {
+                        return Ok(128 + sig as i32);
+                    }
+                }
+                // We don't expect any events with tokens other than those we provided.
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    Ok(0)
+}
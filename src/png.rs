@@ -0,0 +1,226 @@
+//! A minimal PNG encoder, for truecolor images.
+//!
+//! The image data is stored uncompressed, in deflate blocks that are
+//! copied as is. That makes for big files, but needs nothing more than the
+//! checksums.
+//!
+//! See <https://www.w3.org/TR/png/>.
+
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Most bytes a stored deflate block can hold.
+const MAX_STORED: usize = 0xffff;
+
+/// Largest width, height and chunk length a PNG can have, 2^31 - 1.
+const MAX_LENGTH: usize = i32::MAX as usize;
+
+/// Writes the `width` by `height` image to `w`. `rgb` holds the red, green
+/// and blue values of its pixels, row by row. Fails with `InvalidInput`
+/// if the image is too big for a PNG.
+pub(crate) fn write(w: &mut impl Write, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    if width > MAX_LENGTH || height > MAX_LENGTH {
+        return Err(too_big());
+    }
+    assert_eq!(rgb.len(), width * height * 3);
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, no filtering and no
+    // interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // Every row starts with the type of filter it was filtered with, none.
+    let mut data = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        data.push(0);
+        data.extend_from_slice(row);
+    }
+    let data = zlib_stored(&data);
+    if data.len() > MAX_LENGTH {
+        return Err(too_big());
+    }
+
+    w.write_all(&SIGNATURE)?;
+    write_chunk(w, b"IHDR", &header)?;
+    write_chunk(w, b"IDAT", &data)?;
+    write_chunk(w, b"IEND", &[])?;
+    w.flush()
+}
+
+fn too_big() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "image is too big for a PNG")
+}
+
+/// Writes a chunk of `data`, which must not be longer than `MAX_LENGTH`.
+fn write_chunk(w: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32(crc32_update(0xffff_ffff, kind), data);
+    w.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps `data` in a zlib stream of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / MAX_STORED + 1;
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // Deflate with a 32K window, and the check bits that make the header
+    // a multiple of 31.
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Updates a CRC-32 that hasn't been finalized yet with `data`.
+fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc
+}
+
+/// Finishes the CRC-32 of a chunk, with `data` following what `crc`
+/// covers so far.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !crc32_update(crc, data)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums of this many bytes can't overflow before being reduced.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(0xffff_ffff, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(0xffff_ffff, b""), 0);
+        // Continuing a CRC is the same as computing it in one go.
+        assert_eq!(crc32(crc32_update(0xffff_ffff, b"IEND"), b""), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b""), 1);
+        // Long enough for the sums to be reduced along the way.
+        let zeros_then_ff = [vec![0; 5000], vec![0xff; 10_000]].concat();
+        assert_eq!(adler32(&zeros_then_ff), 0xc9ab_eb2b);
+    }
+
+    /// Returns the data of the stored blocks of the zlib stream `z`,
+    /// checking its structure.
+    fn unzlib(z: &[u8]) -> Vec<u8> {
+        assert_eq!(&z[..2], &[0x78, 0x01]);
+        assert_eq!(u16::from_be_bytes([z[0], z[1]]) % 31, 0);
+        let mut rest = &z[2..];
+        let mut data = Vec::new();
+        loop {
+            let last = rest[0];
+            assert!(last <= 1, "not a stored block");
+            let len = u16::from_le_bytes([rest[1], rest[2]]);
+            let nlen = u16::from_le_bytes([rest[3], rest[4]]);
+            assert_eq!(nlen, !len);
+            data.extend_from_slice(&rest[5..5 + len as usize]);
+            rest = &rest[5 + len as usize..];
+            if last == 1 {
+                break;
+            }
+        }
+        assert_eq!(rest, adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn stored_blocks() {
+        assert_eq!(
+            zlib_stored(b""),
+            [0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            zlib_stored(b"ab"),
+            [0x78, 0x01, 1, 2, 0, 0xfd, 0xff, b'a', b'b', 1, 0x26, 0, 0xc4]
+        );
+        for len in &[
+            1,
+            MAX_STORED - 1,
+            MAX_STORED,
+            MAX_STORED + 1,
+            3 * MAX_STORED + 7,
+        ] {
+            let data: Vec<u8> = (0..*len).map(|i| (i % 251) as u8).collect();
+            assert!(unzlib(&zlib_stored(&data)) == data, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn image() {
+        let rgb = [255, 0, 0, 0, 255, 0, 0, 0, 255, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let mut png = Vec::new();
+        write(&mut png, 3, 2, &rgb).unwrap();
+
+        assert_eq!(&png[..8], &SIGNATURE);
+        let mut rest = &png[8..];
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let kind = &rest[4..8];
+            let data = &rest[8..8 + len];
+            let crc = &rest[8 + len..12 + len];
+            assert_eq!(crc, crc32(0xffff_ffff, &rest[4..8 + len]).to_be_bytes());
+            chunks.push((kind.to_vec(), data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        let mut rows = vec![0];
+        rows.extend_from_slice(&rgb[..9]);
+        rows.push(0);
+        rows.extend_from_slice(&rgb[9..]);
+        assert_eq!(unzlib(&chunks[1].1), rows);
+        assert!(chunks[2].1.is_empty());
+    }
+
+    #[test]
+    fn too_big_images() {
+        let mut out = Vec::new();
+        for &(width, height) in &[(MAX_LENGTH + 1, 0), (0, MAX_LENGTH + 1)] {
+            let err = write(&mut out, width, height, &[]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(out.is_empty());
+        write(&mut out, MAX_LENGTH, 0, &[]).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::libc;
+use nix::pty::PtyMaster;
+use nix::sys::signal::{self, Signal};
+use nix::sys::termios::{self, SpecialCharacterIndices};
+use nix::unistd::{self, Pid};
+
+use crate::filter::Filters;
+use crate::pty::{wait_status, ExitStatus};
+use crate::signal::{read_signals, TERMINATION_SIGNALS};
+use crate::term::{self, copy_winsize};
+
+const STDIN: Token = Token(0);
+const PTY_MASTER: Token = Token(1);
+const SIGNAL: Token = Token(2);
+const STDOUT: Token = Token(3);
+
+const CTRL_D: u8 = 0x04;
+
+/// Amount of data read in one direction but not written yet beyond which
+/// we stop reading, until the other side catches up.
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Options for proxying to a PTY.
+#[derive(Clone, Debug)]
+pub struct ProxyOptions {
+    /// How long the program has to exit after a termination signal was
+    /// forwarded to it, before it is killed. 10 seconds by default.
+    pub kill_grace: Duration,
+}
+
+impl Default for ProxyOptions {
+    fn default() -> ProxyOptions {
+        ProxyOptions {
+            kill_grace: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Puts a file descriptor in non-blocking mode for as long as it is alive,
+/// restoring its original flags when dropped.
+pub(crate) struct NonBlocking {
+    fd: RawFd,
+    flags: OFlag,
+}
+
+impl NonBlocking {
+    pub(crate) fn new(fd: RawFd) -> Result<NonBlocking, nix::Error> {
+        let flags = OFlag::from_bits_truncate(fcntl::fcntl(fd, FcntlArg::F_GETFL)?);
+        fcntl::fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        Ok(NonBlocking { fd, flags })
+    }
+}
+
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        let _ = fcntl::fcntl(self.fd, FcntlArg::F_SETFL(self.flags));
+    }
+}
+
+/// Data flowing from one non-blocking file descriptor to another.
+struct Pipe {
+    src: RawFd,
+    dst: RawFd,
+    /// Data read from `src` that `dst` wasn't ready to take yet.
+    pending: Vec<u8>,
+    /// Whether `src` reached end of file, or hung up.
+    eof: bool,
+}
+
+impl Pipe {
+    fn new(src: RawFd, dst: RawFd) -> Pipe {
+        Pipe {
+            src,
+            dst,
+            pending: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Reads from `src` until it would block, or enough data is pending.
+    /// Every chunk read is passed through `filter`, and what it returns is
+    /// what becomes pending.
+    fn fill(
+        &mut self,
+        mut filter: impl FnMut(&[u8]) -> io::Result<Vec<u8>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut buf = [0u8; 4096];
+        while !self.eof && self.pending.len() < MAX_PENDING {
+            match unistd::read(self.src, &mut buf) {
+                // A PTY master reports EIO once the slave side is closed.
+                Ok(0) | Err(nix::Error::Sys(Errno::EIO)) => self.eof = true,
+                Ok(n) => {
+                    let data = filter(&buf[..n])?;
+                    self.pending.extend_from_slice(&data);
+                }
+                Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the buffer of pending data to `dst`, as much as it takes
+    /// without blocking.
+    fn write_buffer_to(&mut self) -> Result<(), nix::Error> {
+        while !self.pending.is_empty() {
+            match unistd::write(self.dst, &self.pending) {
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the end of file character of the PTY `master`, typically Ctrl-D.
+fn eof_char(master: RawFd) -> u8 {
+    termios::tcgetattr(master)
+        .map(|termios| termios.control_chars[SpecialCharacterIndices::VEOF as usize])
+        .unwrap_or(CTRL_D)
+}
+
+/// Returns the foreground process group of the terminal of the PTY `master`,
+/// or the process group the `child` leads if it can't be told.
+fn foreground_group(master: RawFd, child: Pid) -> Pid {
+    unistd::tcgetpgrp(master).unwrap_or(child)
+}
+
+/// Sends `sig` to the process group `pgrp`, unless it is already gone.
+fn kill_group(pgrp: Pid, sig: Signal) -> Result<(), nix::Error> {
+    match signal::killpg(pgrp, sig) {
+        Err(nix::Error::Sys(Errno::ESRCH)) => Ok(()),
+        res => res,
+    }
+}
+
+/// Proxies between stdin of this process to the master terminal device.
+/// If a `child` is given, returns its exit status once the slave side hangs up,
+/// otherwise a status of 0. The end of stdin is passed on to the slave as its
+/// end of file character, and output is read until the slave hangs up.
+/// Other signals received on the `signals` pipe are forwarded to the
+/// foreground process group of the slave. Once one of the
+/// `TERMINATION_SIGNALS` was forwarded, the program is killed if it hasn't
+/// exited after `opts.kill_grace`. Without a `child`, termination signals
+/// end the session right away, as if it was killed by them.
+/// Everything copied between the two goes through the `filters` first, and
+/// they are told how the session ended.
+///
+/// All file descriptors involved are switched to non-blocking mode for the
+/// duration of the session, so neither direction can hold up the other.
+pub(crate) fn proxy_term(
+    stdin: RawFd,
+    pty_master: &PtyMaster,
+    signals: RawFd,
+    child: Option<Pid>,
+    filters: &mut Filters,
+    opts: &ProxyOptions,
+) -> Result<ExitStatus, Box<dyn Error>> {
+    let status = proxy_loop(stdin, pty_master, signals, child, filters, opts)?;
+    filters.exit(&status)?;
+    Ok(status)
+}
+
+fn proxy_loop(
+    stdin: RawFd,
+    pty_master: &PtyMaster,
+    signals: RawFd,
+    child: Option<Pid>,
+    filters: &mut Filters,
+    opts: &ProxyOptions,
+) -> Result<ExitStatus, Box<dyn Error>> {
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(128);
+    let pty_master_fd = pty_master.as_raw_fd();
+    let stdout = io::stdout().as_raw_fd();
+
+    // Anything buffered by the standard library must go out first.
+    io::stdout().flush()?;
+
+    // Stdin and stdout often share the same open file, and with it their
+    // flags. So the flags they had first are restored last, by dropping the
+    // guards in the reverse order of their creation.
+    let stdin_nonblocking = NonBlocking::new(stdin)?;
+    let stdout_nonblocking = NonBlocking::new(stdout)?;
+    let master_nonblocking = NonBlocking::new(pty_master_fd)?;
+    let nonblocking = (master_nonblocking, stdout_nonblocking, stdin_nonblocking);
+
+    // Register stdin, wait for it to be readable. Regular files and the
+    // like can't be polled, but are always ready to be read anyway.
+    let stdin_registered =
+        match poll
+            .registry()
+            .register(&mut SourceFd(&stdin), STDIN, Interest::READABLE)
+        {
+            Ok(()) => true,
+            Err(ref err) if err.raw_os_error() == Some(libc::EPERM) => false,
+            Err(err) => return Err(err.into()),
+        };
+    // Resizes only mean something if stdin is a terminal.
+    let interactive = unistd::isatty(stdin)?;
+
+    // Register PTY master, wait for it to be readable. It is also waited
+    // on to be writable whenever input for it is pending.
+    poll.registry().register(
+        &mut SourceFd(&pty_master_fd),
+        PTY_MASTER,
+        Interest::READABLE,
+    )?;
+
+    // Register the signal pipe, wait for a signal to arrive.
+    poll.registry()
+        .register(&mut SourceFd(&signals), SIGNAL, Interest::READABLE)?;
+
+    // Stdout is only waited on to be writable while output for it is
+    // pending, which only ever happens if it can be polled.
+    let mut stdout_registered = false;
+
+    let mut input = Pipe::new(stdin, pty_master_fd);
+    let mut output = Pipe::new(pty_master_fd, stdout);
+    let mut stdin_closed = false;
+    // The last byte of input read, to know whether a line was left unfinished.
+    let mut last_input = None;
+    // When the program will be killed, if it was asked to terminate.
+    let mut kill_deadline = None;
+
+    filters.start(pty_master_fd);
+    loop {
+        // The file descriptors are edge-triggered, so read until they would
+        // block, and write as much as they take.
+        input.fill(|buf| {
+            let data = filters.input(buf)?;
+            last_input = data.last().copied().or(last_input);
+            Ok(data)
+        })?;
+        if input.eof && !stdin_closed {
+            // Pass the end of our input on to the program, and keep going
+            // until it is done with its output.
+            if stdin_registered {
+                poll.registry().deregister(&mut SourceFd(&stdin))?;
+            }
+            stdin_closed = true;
+            let veof = eof_char(pty_master_fd);
+            // The first end of file only ends a partially typed line.
+            if last_input.is_some() && last_input != Some(b'\n') {
+                input.pending.push(veof);
+            }
+            input.pending.push(veof);
+        }
+        input.write_buffer_to()?;
+        output.fill(|buf| filters.output(buf))?;
+        output.write_buffer_to()?;
+
+        if output.eof {
+            // The slave side hung up, and everything it wrote has been read.
+            // Whatever output is left is written out blocking.
+            drop(nonblocking);
+            io::stdout().write_all(&output.pending)?;
+            io::stdout().flush()?;
+            return match child {
+                Some(pid) => wait_status(pid),
+                None => Ok(ExitStatus::exited(0)),
+            };
+        }
+
+        let interest = if input.pending.is_empty() {
+            Interest::READABLE
+        } else {
+            Interest::READABLE | Interest::WRITABLE
+        };
+        poll.registry()
+            .reregister(&mut SourceFd(&pty_master_fd), PTY_MASTER, interest)?;
+
+        if output.pending.is_empty() == stdout_registered {
+            if stdout_registered {
+                poll.registry().deregister(&mut SourceFd(&stdout))?;
+            } else {
+                poll.registry()
+                    .register(&mut SourceFd(&stdout), STDOUT, Interest::WRITABLE)?;
+            }
+            stdout_registered = !stdout_registered;
+        }
+
+        // Poll for events, blocking until we get an event. Unless stdin can't
+        // be polled and there is room to read more of it.
+        // Signals arriving during the poll interrupt it, so just poll again.
+        // Don't sleep past the deadline to kill the program either.
+        let timeout = if !stdin_registered && !stdin_closed && input.pending.is_empty() {
+            Some(Duration::from_secs(0))
+        } else {
+            kill_deadline
+                .map(|deadline: Instant| deadline.saturating_duration_since(Instant::now()))
+        };
+        match poll.poll(&mut events, timeout) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        }
+
+        if let (Some(deadline), Some(pid)) = (kill_deadline, child) {
+            if Instant::now() >= deadline {
+                // Take down whatever is left of the program, including the
+                // process that leads its session.
+                kill_group(foreground_group(pty_master_fd, pid), Signal::SIGKILL)?;
+                kill_group(pid, Signal::SIGKILL)?;
+                kill_deadline = None;
+            }
+        }
+
+        for event in events.iter() {
+            match event.token() {
+                // Data is moved in both directions above, whatever is ready.
+                STDIN | PTY_MASTER | STDOUT => {}
+                SIGNAL => {
+                    for sig in read_signals(signals)? {
+                        match sig {
+                            Signal::SIGWINCH if interactive => {
+                                copy_winsize(stdin, pty_master_fd)?;
+                                filters.resize(&term::get_winsize(pty_master_fd)?)?;
+                            }
+                            Signal::SIGWINCH => {}
+                            sig => match child {
+                                Some(pid) => {
+                                    kill_group(foreground_group(pty_master_fd, pid), sig)?;
+                                    if TERMINATION_SIGNALS.contains(&sig) && kill_deadline.is_none()
+                                    {
+                                        kill_deadline = Some(Instant::now() + opts.kill_grace);
+                                    }
+                                }
+                                None if TERMINATION_SIGNALS.contains(&sig) => {
+                                    return Ok(ExitStatus::signaled(sig, false))
+                                }
+                                None => {}
+                            },
+                        }
+                    }
+                }
+                // We don't expect any events with tokens other than those we provided.
+                _ => unreachable!(),
+            }
+        }
+    }
+}
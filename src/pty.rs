@@ -0,0 +1,291 @@
+use std::error::Error;
+use std::ffi::CString;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::fcntl::{self, OFlag};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal};
+use nix::sys::stat::Mode;
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+use nix::unistd::{self, ForkResult, Pid};
+use nix::{libc, pty};
+
+use crate::filter::Filters;
+use crate::proxy::{proxy_term, ProxyOptions};
+use crate::term::{self, TermSettings, Winsize};
+
+// Makes the given terminal the controlling terminal of the calling process.
+nix::ioctl_write_int_bad!(tiocsctty, libc::TIOCSCTTY);
+
+/// A PTY master / slave pair.
+pub struct PtyPair {
+    pub master: pty::PtyMaster,
+    pub slave_name: String,
+}
+
+impl PtyPair {
+    /// Opens and returns a new PTY pair.
+    /// The pair contains the PTY master FD and the slave path.
+    pub fn open() -> Result<PtyPair, Box<dyn Error>> {
+        // Open a new PTY master.
+        let master = pty::posix_openpt(OFlag::O_RDWR)?;
+
+        // Allow a slave to be generated for it.
+        pty::grantpt(&master)?;
+        pty::unlockpt(&master)?;
+
+        // Get the name of the slave.
+        let slave_name = unsafe { pty::ptsname(&master) }?;
+
+        Ok(PtyPair { master, slave_name })
+    }
+
+    /// Sets the window size of the PTY.
+    pub fn resize(&self, winsize: &Winsize) -> Result<(), nix::Error> {
+        term::set_winsize(self.master.as_raw_fd(), winsize)
+    }
+
+    /// Changes the settings of the PTY, which the program running on the
+    /// slave sees. Programs may change them again themselves.
+    pub fn configure(&self, settings: &TermSettings) -> Result<(), nix::Error> {
+        settings.apply_to(self.master.as_raw_fd())
+    }
+
+    /// Runs `cmd` in a new session with the PTY slave as its controlling
+    /// terminal and stdin, stdout and stderr.
+    /// Returns the pid of the child process.
+    pub fn spawn(&self, cmd: &[String]) -> Result<Pid, Box<dyn Error>> {
+        if cmd.is_empty() {
+            return Err("no command given".into());
+        }
+        let args = cmd
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Open the slave before forking so the master never observes a hangup
+        // in the window between the fork and the child opening it.
+        let slave = fcntl::open(
+            self.slave_name.as_str(),
+            OFlag::O_RDWR | OFlag::O_NOCTTY,
+            Mode::empty(),
+        )?;
+
+        match unistd::fork()? {
+            ForkResult::Parent { child } => {
+                unistd::close(slave)?;
+                Ok(child)
+            }
+            ForkResult::Child => {
+                let err = exec_child(self.master.as_raw_fd(), slave, &args);
+                eprintln!("ptyme: failed to execute {}: {}", cmd[0], err);
+                process::exit(127);
+            }
+        }
+    }
+
+    /// Proxies between `stdin` and the PTY master until the slave side
+    /// hangs up. See `signal::signal_pipe` for the `signals` pipe.
+    ///
+    /// If a `child` is given, returns its exit status once the slave side
+    /// hangs up, otherwise a status of 0. Signals other than SIGWINCH are
+    /// forwarded to the foreground process group of the slave, and after a
+    /// termination signal the child is killed if it doesn't exit within
+    /// `opts.kill_grace`. Without a child, termination signals end the
+    /// session as if it was killed by them.
+    ///
+    /// Everything passing through goes through the `filters` first, which
+    /// can log, record or change it.
+    pub fn proxy(
+        &self,
+        stdin: RawFd,
+        signals: RawFd,
+        child: Option<Pid>,
+        filters: &mut Filters,
+        opts: &ProxyOptions,
+    ) -> Result<ExitStatus, Box<dyn Error>> {
+        proxy_term(stdin, &self.master, signals, child, filters, opts)
+    }
+}
+
+/// Sets up the PTY slave as the controlling terminal of the current (child)
+/// process and replaces the process image with `args`.
+/// Only returns if something went wrong.
+fn exec_child(master: RawFd, slave: RawFd, args: &[CString]) -> nix::Error {
+    let res = (|| {
+        unistd::close(master)?;
+
+        // Ignored signals and the signal mask survive exec, reset them to
+        // what programs expect rather than leaving ptyme's. SIGPIPE is
+        // ignored by Rust programs, for one.
+        let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+        for sig in Signal::iterator() {
+            if sig != Signal::SIGKILL && sig != Signal::SIGSTOP {
+                unsafe { signal::sigaction(sig, &default) }?;
+            }
+        }
+        signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None)?;
+
+        // Start a new session so the slave can become our controlling terminal.
+        unistd::setsid()?;
+        unsafe { tiocsctty(slave, 0) }?;
+
+        for fd in 0..3 {
+            unistd::dup2(slave, fd)?;
+        }
+        if slave > 2 {
+            unistd::close(slave)?;
+        }
+
+        let argv: Vec<_> = args.iter().map(CString::as_c_str).collect();
+        unistd::execvp(&args[0], &argv)
+    })();
+
+    match res {
+        Ok(void) => match void {},
+        Err(err) => err,
+    }
+}
+
+/// How a child process terminated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExitStatus {
+    /// The exit code, or 128 + the signal number if the process was killed
+    /// by a signal, like shells report it.
+    pub code: i32,
+    /// The signal that killed the process, if any.
+    pub signal: Option<Signal>,
+    /// Whether the process dumped core when it was killed.
+    pub core_dumped: bool,
+}
+
+impl ExitStatus {
+    /// Returns the status of a process that exited with `code`.
+    pub fn exited(code: i32) -> ExitStatus {
+        ExitStatus {
+            code,
+            signal: None,
+            core_dumped: false,
+        }
+    }
+
+    /// Returns the status of a process that was killed by `signal`.
+    pub fn signaled(signal: Signal, core_dumped: bool) -> ExitStatus {
+        ExitStatus {
+            code: 128 + signal as i32,
+            signal: Some(signal),
+            core_dumped,
+        }
+    }
+}
+
+/// Waits for the child `pid` to terminate and returns how it terminated.
+pub fn wait_status(pid: Pid) -> Result<ExitStatus, Box<dyn Error>> {
+    loop {
+        match wait::waitpid(pid, None)? {
+            WaitStatus::Exited(_, code) => return Ok(ExitStatus::exited(code)),
+            WaitStatus::Signaled(_, signal, core_dumped) => {
+                return Ok(ExitStatus::signaled(signal, core_dumped))
+            }
+            // Stops and continues aren't terminations, keep waiting.
+            _ => {}
+        }
+    }
+}
+
+/// Waits for the child `pid` to terminate and returns its exit status,
+/// or 128 + the signal number if it was killed by a signal.
+pub fn wait_child(pid: Pid) -> Result<i32, Box<dyn Error>> {
+    Ok(wait_status(pid)?.code)
+}
+
+/// How long a child that was hung up gets to exit, before it is sent
+/// SIGTERM, and then SIGKILL.
+const HANGUP_GRACE: Duration = Duration::from_millis(500);
+
+/// Waits for the child `pid` to terminate after its terminal was closed,
+/// and returns its exit status like `wait_child`. Programs that ignore the
+/// hangup don't get to run on: their process group is sent SIGTERM, and
+/// then SIGKILL, and once they are gone, that is returned as an error.
+pub fn reap_hung_up(pid: Pid) -> Result<i32, Box<dyn Error>> {
+    let mut status = wait_until(pid, Instant::now() + HANGUP_GRACE)?;
+    let mut killed_with = None;
+    for &sig in &[Signal::SIGTERM, Signal::SIGKILL] {
+        if status.is_some() {
+            break;
+        }
+        // The child leads its own session, so its group is its own as well.
+        // It can't be gone yet, as it hasn't been waited for.
+        signal::killpg(pid, sig)?;
+        killed_with = Some(sig);
+        status = match sig {
+            Signal::SIGKILL => Some(wait_status(pid)?),
+            _ => wait_until(pid, Instant::now() + HANGUP_GRACE)?,
+        };
+    }
+    match killed_with {
+        Some(sig) => {
+            Err(format!("program didn't exit when hung up, killed it with {:?}", sig).into())
+        }
+        None => Ok(status.map_or(0, |status| status.code)),
+    }
+}
+
+/// Waits for the child `pid` to terminate until `deadline`, and returns how
+/// it terminated if it did.
+fn wait_until(pid: Pid, deadline: Instant) -> Result<Option<ExitStatus>, Box<dyn Error>> {
+    loop {
+        match wait::waitpid(pid, Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::Exited(_, code) => return Ok(Some(ExitStatus::exited(code))),
+            WaitStatus::Signaled(_, signal, core_dumped) => {
+                return Ok(Some(ExitStatus::signaled(signal, core_dumped)))
+            }
+            _ if Instant::now() >= deadline => return Ok(None),
+            _ => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn child_gets_default_signal_state() {
+        // Ignore SIGPIPE, as the Rust runtime does, and block a signal.
+        unsafe { signal::signal(Signal::SIGPIPE, SigHandler::SigIgn) }.unwrap();
+        let mut blocked = SigSet::empty();
+        blocked.add(Signal::SIGUSR2);
+        signal::pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&blocked), None).unwrap();
+
+        let pty_pair = PtyPair::open().unwrap();
+        let cmd = ["cat".to_string(), "/proc/self/status".to_string()];
+        let child = pty_pair.spawn(&cmd).unwrap();
+        let mut status = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match unistd::read(pty_pair.master.as_raw_fd(), &mut buf) {
+                Ok(0) | Err(nix::Error::Sys(nix::errno::Errno::EIO)) => break,
+                Ok(n) => status.extend_from_slice(&buf[..n]),
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+                Err(err) => panic!("{}", err),
+            }
+        }
+        signal::pthread_sigmask(SigmaskHow::SIG_UNBLOCK, Some(&blocked), None).unwrap();
+        assert_eq!(wait_child(child).unwrap(), 0);
+
+        let status = String::from_utf8_lossy(&status);
+        let mask = |name: &str| {
+            let line = status.lines().find(|line| line.starts_with(name)).unwrap();
+            u64::from_str_radix(line[name.len()..].trim(), 16).unwrap()
+        };
+        // Only the standard signals, the C library reserves some of the
+        // real-time ones.
+        let standard = (1 << 31) - 1;
+        assert_eq!(mask("SigIgn:") & standard, 0);
+        assert_eq!(mask("SigBlk:") & standard, 0);
+    }
+}
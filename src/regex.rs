@@ -0,0 +1,875 @@
+//! A small regular expression engine for matching PTY output.
+//!
+//! Patterns are compiled to a program for a Pike VM, which tries every way
+//! a pattern can match at once instead of backtracking, so matching takes
+//! time linear in the input and constant stack space.
+//!
+//! Supported syntax: literals, `.`, character classes (`[a-z]`, `[^0-9]`),
+//! the escapes `\d \w \s \D \W \S \n \r \t \e \xHH` and escaped
+//! metacharacters, anchors `^` and `$` (which match at line boundaries),
+//! groups `(...)` and non-capturing groups `(?:...)`, alternation `|` and
+//! the quantifiers `* + ? {n} {n,} {n,m}`, each optionally lazy with a
+//! trailing `?`. Patterns match bytes, so `.` matches a single byte of a
+//! multi-byte UTF-8 character.
+
+use std::error::Error;
+use std::fmt;
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    prog: Vec<Inst>,
+    groups: usize,
+}
+
+/// The groups captured by a match. Group 0 is the whole match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures {
+    groups: Vec<Option<Vec<u8>>>,
+    end: usize,
+}
+
+impl Captures {
+    /// Returns the bytes captured by group `i`, if it took part in the match.
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        self.groups.get(i).and_then(|g| g.as_deref())
+    }
+
+    /// Returns group `i` as a string, replacing invalid UTF-8.
+    pub fn get_str(&self, i: usize) -> Option<String> {
+        self.get(i).map(|g| String::from_utf8_lossy(g).into_owned())
+    }
+
+    /// Number of groups, including group 0.
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Always false, as there is at least group 0.
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Offset just past the end of the match in the searched input.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// An error in the syntax of a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError {
+    msg: &'static str,
+    pos: usize,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern at offset {}: {}", self.pos, self.msg)
+    }
+}
+
+impl Error for RegexError {}
+
+/// Upper bound on the size of a compiled pattern, which counted
+/// repetitions multiply.
+const MAX_INSTS: usize = 10_000;
+/// Upper bound on the counts in `{n,m}`.
+const MAX_REPEAT: usize = 1000;
+/// How deeply groups may be nested, which the parser and compiler recurse
+/// on.
+const MAX_DEPTH: usize = 250;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Byte(u8),
+    /// Any byte but a newline.
+    Any,
+    Class {
+        ranges: Vec<(u8, u8)>,
+        negated: bool,
+    },
+    LineStart,
+    LineEnd,
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+impl Node {
+    /// Returns whether this node always matches exactly one byte, and
+    /// whether it matches `b`.
+    fn single_byte(&self, b: u8) -> Option<bool> {
+        match self {
+            Node::Byte(c) => Some(*c == b),
+            Node::Any => Some(b != b'\n'),
+            Node::Class { ranges, negated } => {
+                Some(ranges.iter().any(|&(lo, hi)| lo <= b && b <= hi) != *negated)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An instruction of a compiled pattern.
+#[derive(Debug, Clone)]
+enum Inst {
+    /// Consumes a byte matched by the node, which matches single bytes.
+    Byte(Node),
+    LineStart,
+    LineEnd,
+    /// Continues at both targets, preferring the first.
+    Split(usize, usize),
+    Jump(usize),
+    /// Records the current offset in a capture slot.
+    Save(usize),
+    Match,
+}
+
+/// Capture slots, the start and end offset of each group in turn.
+type Slots = Vec<Option<usize>>;
+
+impl Regex {
+    /// Compiles `pattern`.
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let mut parser = Parser {
+            input: pattern.as_bytes(),
+            pos: 0,
+            groups: 0,
+            depth: 0,
+        };
+        let root = parser.alternation()?;
+        if parser.pos != parser.input.len() {
+            return Err(parser.error("unmatched ')'"));
+        }
+
+        let mut prog = vec![Inst::Save(0)];
+        compile(&root, &mut prog);
+        prog.push(Inst::Save(1));
+        prog.push(Inst::Match);
+        if prog.len() > MAX_INSTS {
+            return Err(RegexError {
+                msg: "pattern too large",
+                pos: 0,
+            });
+        }
+
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            prog,
+            groups: parser.groups,
+        })
+    }
+
+    /// The pattern this was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns whether the pattern matches anywhere in `input`.
+    pub fn is_match(&self, input: &[u8]) -> bool {
+        self.captures(input).is_some()
+    }
+
+    /// Finds the leftmost match in `input` and returns its groups. Where
+    /// several matches start there, the one a backtracking matcher would
+    /// find first wins.
+    ///
+    /// All candidate matches are run in lockstep, one input byte at a time,
+    /// so this takes time linear in the length of `input`.
+    pub fn captures(&self, input: &[u8]) -> Option<Captures> {
+        let mut current = Threads::new(self.prog.len());
+        let mut next = Threads::new(self.prog.len());
+        let mut stack = Vec::new();
+        let mut matched: Option<Slots> = None;
+
+        for pos in 0..=input.len() {
+            // Start a match here, unless an earlier one already succeeded.
+            // It ranks below everything that started before.
+            if matched.is_none() {
+                let mut slots = vec![None; 2 * (self.groups + 1)];
+                self.add_thread(&mut current, 0, &mut slots, input, pos, &mut stack);
+            }
+            if matched.is_some() && current.pcs.is_empty() {
+                break;
+            }
+
+            for (i, &pc) in current.pcs.iter().enumerate() {
+                match &self.prog[pc] {
+                    Inst::Byte(node) => {
+                        if input
+                            .get(pos)
+                            .is_some_and(|&b| node.single_byte(b) == Some(true))
+                        {
+                            let mut slots = current.slots[i].clone();
+                            self.add_thread(
+                                &mut next,
+                                pc + 1,
+                                &mut slots,
+                                input,
+                                pos + 1,
+                                &mut stack,
+                            );
+                        }
+                    }
+                    Inst::Match => {
+                        // Threads after this one are less preferred.
+                        matched = Some(current.slots[i].clone());
+                        break;
+                    }
+                    _ => unreachable!(),
+                }
+            }
+
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        matched.map(|slots| Captures {
+            groups: slots
+                .chunks(2)
+                .map(|group| match *group {
+                    [Some(start), Some(end)] => Some(input[start..end].to_vec()),
+                    _ => None,
+                })
+                .collect(),
+            end: slots[1].unwrap_or(0),
+        })
+    }
+
+    /// Adds a thread at `pc` to `threads`, following jumps, splits, saves
+    /// and assertions at `pos` up to the instructions that consume input or
+    /// match, in order of preference.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        slots: &mut Slots,
+        input: &[u8],
+        pos: usize,
+        stack: &mut Vec<Frame>,
+    ) {
+        stack.push(Frame::Explore(pc));
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::Explore(pc) => pc,
+                Frame::Restore(slot, saved) => {
+                    slots[slot] = saved;
+                    continue;
+                }
+            };
+            // A thread that got here first has priority.
+            if !threads.visit(pc) {
+                continue;
+            }
+            match self.prog[pc] {
+                Inst::Jump(to) => stack.push(Frame::Explore(to)),
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Explore(second));
+                    stack.push(Frame::Explore(first));
+                }
+                Inst::Save(slot) => {
+                    stack.push(Frame::Restore(slot, slots[slot]));
+                    slots[slot] = Some(pos);
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::LineStart => {
+                    if pos == 0 || input[pos - 1] == b'\n' {
+                        stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                Inst::LineEnd => {
+                    if pos == input.len() || input[pos] == b'\n' || input[pos] == b'\r' {
+                        stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                Inst::Byte(_) | Inst::Match => {
+                    threads.pcs.push(pc);
+                    threads.slots.push(slots.clone());
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// Appends the instructions matching `node` to `prog`.
+fn compile(node: &Node, prog: &mut Vec<Inst>) {
+    // Counted repetitions can blow up, give up once the result is too
+    // large to be used anyway.
+    if prog.len() > MAX_INSTS {
+        return;
+    }
+    match node {
+        Node::Byte(_) | Node::Any | Node::Class { .. } => prog.push(Inst::Byte(node.clone())),
+        Node::LineStart => prog.push(Inst::LineStart),
+        Node::LineEnd => prog.push(Inst::LineEnd),
+        Node::Group(inner, None) => compile(inner, prog),
+        Node::Group(inner, Some(i)) => {
+            prog.push(Inst::Save(2 * i));
+            compile(inner, prog);
+            prog.push(Inst::Save(2 * i + 1));
+        }
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, prog);
+            }
+        }
+        Node::Alt(alts) => {
+            let mut jumps = Vec::new();
+            for (i, alt) in alts.iter().enumerate() {
+                let split = prog.len();
+                if i + 1 < alts.len() {
+                    prog.push(Inst::Split(split + 1, 0));
+                }
+                compile(alt, prog);
+                if i + 1 < alts.len() {
+                    jumps.push(prog.len());
+                    prog.push(Inst::Jump(0));
+                    let next = prog.len();
+                    prog[split] = Inst::Split(split + 1, next);
+                }
+            }
+            let end = prog.len();
+            for jump in jumps {
+                prog[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat {
+            node,
+            min,
+            max,
+            greedy,
+        } => {
+            let split = |body, out| {
+                if *greedy {
+                    Inst::Split(body, out)
+                } else {
+                    Inst::Split(out, body)
+                }
+            };
+            for _ in 0..*min {
+                compile(node, prog);
+            }
+            match max {
+                None => {
+                    let start = prog.len();
+                    prog.push(Inst::Match);
+                    compile(node, prog);
+                    prog.push(Inst::Jump(start));
+                    let out = prog.len();
+                    prog[start] = split(start + 1, out);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        if prog.len() > MAX_INSTS {
+                            return;
+                        }
+                        splits.push(prog.len());
+                        prog.push(Inst::Match);
+                        compile(node, prog);
+                    }
+                    let out = prog.len();
+                    for start in splits {
+                        prog[start] = split(start + 1, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A step of `Regex::add_thread`.
+enum Frame {
+    Explore(usize),
+    /// Puts back the value a capture slot had before a `Save`.
+    Restore(usize, Option<usize>),
+}
+
+/// The threads of a match at one input offset, in order of preference.
+struct Threads {
+    pcs: Vec<usize>,
+    slots: Vec<Slots>,
+    /// The generation in which each instruction was last visited.
+    visited: Vec<usize>,
+    generation: usize,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            pcs: Vec::new(),
+            slots: Vec::new(),
+            visited: vec![0; len],
+            generation: 1,
+        }
+    }
+
+    /// Marks `pc` as visited, returning whether it wasn't already.
+    fn visit(&mut self, pc: usize) -> bool {
+        if self.visited[pc] == self.generation {
+            return false;
+        }
+        self.visited[pc] = self.generation;
+        true
+    }
+
+    fn clear(&mut self) {
+        self.pcs.clear();
+        self.slots.clear();
+        self.generation += 1;
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    groups: usize,
+    // Groups the parser is in.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &'static str) -> RegexError {
+        RegexError { msg, pos: self.pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, RegexError> {
+        let mut alts = vec![self.concat()?];
+        while self.eat(b'|') {
+            alts.push(self.concat()?);
+        }
+        Ok(if alts.len() == 1 {
+            alts.pop().unwrap()
+        } else {
+            Node::Alt(alts)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+        while let Some(b) = self.peek() {
+            if b == b'|' || b == b')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(Node::Concat(nodes))
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node, RegexError> {
+        let (min, max) = match self.peek() {
+            Some(b'*') => (0, None),
+            Some(b'+') => (1, None),
+            Some(b'?') => (0, Some(1)),
+            Some(b'{') => match self.counted()? {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        if let Node::LineStart | Node::LineEnd = atom {
+            return Err(self.error("nothing to repeat"));
+        }
+        self.pos += 1;
+        let greedy = !self.eat(b'?');
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`, leaving `pos` on the closing brace.
+    /// Returns `None`, consuming nothing, if the brace doesn't start one, in
+    /// which case it is a literal.
+    fn counted(&mut self) -> Result<Option<(usize, Option<usize>)>, RegexError> {
+        let rest = &self.input[self.pos + 1..];
+        let close = match rest.iter().position(|&b| b == b'}') {
+            Some(close) => close,
+            None => return Ok(None),
+        };
+        let body = std::str::from_utf8(&rest[..close]).unwrap_or("");
+        // Counts too big for a usize are too large all the same.
+        let count = |n: &str| {
+            (!n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| n.parse().unwrap_or(usize::MAX))
+        };
+        let mut parts = body.splitn(2, ',');
+        let min = match parts.next().and_then(count) {
+            Some(min) => min,
+            None => return Ok(None),
+        };
+        let max = match parts.next() {
+            None => Some(min),
+            Some("") => None,
+            Some(max) => match count(max) {
+                Some(max) if max >= min => Some(max),
+                _ => return Err(self.error("invalid repetition bounds")),
+            },
+        };
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+            return Err(self.error("repetition count too large"));
+        }
+        self.pos += close + 1;
+        Ok(Some((min, max)))
+    }
+
+    fn atom(&mut self) -> Result<Node, RegexError> {
+        let b = self
+            .peek()
+            .ok_or_else(|| self.error("unexpected end of pattern"))?;
+        self.pos += 1;
+        match b {
+            b'.' => Ok(Node::Any),
+            b'^' => Ok(Node::LineStart),
+            b'$' => Ok(Node::LineEnd),
+            b'(' => {
+                if self.depth == MAX_DEPTH {
+                    return Err(RegexError {
+                        msg: "groups nested too deeply",
+                        pos: self.pos - 1,
+                    });
+                }
+                let index = if self.input[self.pos..].starts_with(b"?:") {
+                    self.pos += 2;
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                self.depth += 1;
+                let inner = self.alternation()?;
+                self.depth -= 1;
+                if !self.eat(b')') {
+                    return Err(self.error("missing ')'"));
+                }
+                Ok(Node::Group(Box::new(inner), index))
+            }
+            b'[' => self.class(),
+            b'\\' => self.escape(),
+            b'*' | b'+' | b'?' => Err(self.error("nothing to repeat")),
+            b => Ok(Node::Byte(b)),
+        }
+    }
+
+    /// Parses a bracketed class, after the opening bracket.
+    fn class(&mut self) -> Result<Node, RegexError> {
+        let negated = self.eat(b'^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let b = self.peek().ok_or_else(|| self.error("missing ']'"))?;
+            self.pos += 1;
+            if b == b']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if b == b'\\' {
+                match self.escape()? {
+                    Node::Byte(b) => b,
+                    Node::Class {
+                        ranges: class,
+                        negated: false,
+                    } => {
+                        ranges.extend(class);
+                        continue;
+                    }
+                    _ => return Err(self.error("negated escape in class")),
+                }
+            } else {
+                b
+            };
+            let is_range = self.peek() == Some(b'-')
+                && self.input.get(self.pos + 1).is_some_and(|&b| b != b']');
+            if is_range {
+                self.pos += 1;
+                let hi = self.peek().ok_or_else(|| self.error("missing ']'"))?;
+                self.pos += 1;
+                let hi = if hi == b'\\' {
+                    match self.escape()? {
+                        Node::Byte(b) => b,
+                        _ => return Err(self.error("class in range")),
+                    }
+                } else {
+                    hi
+                };
+                if hi < lo {
+                    return Err(self.error("invalid range"));
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    /// Parses an escape, after the backslash.
+    fn escape(&mut self) -> Result<Node, RegexError> {
+        let b = self
+            .peek()
+            .ok_or_else(|| self.error("trailing backslash"))?;
+        self.pos += 1;
+        let class = |ranges: &[(u8, u8)], negated| Node::Class {
+            ranges: ranges.to_vec(),
+            negated,
+        };
+        const DIGIT: &[(u8, u8)] = &[(b'0', b'9')];
+        const WORD: &[(u8, u8)] = &[(b'0', b'9'), (b'A', b'Z'), (b'a', b'z'), (b'_', b'_')];
+        const SPACE: &[(u8, u8)] = &[(b' ', b' '), (b'\t', b'\r')];
+        Ok(match b {
+            b'd' => class(DIGIT, false),
+            b'D' => class(DIGIT, true),
+            b'w' => class(WORD, false),
+            b'W' => class(WORD, true),
+            b's' => class(SPACE, false),
+            b'S' => class(SPACE, true),
+            b'n' => Node::Byte(b'\n'),
+            b'r' => Node::Byte(b'\r'),
+            b't' => Node::Byte(b'\t'),
+            b'e' => Node::Byte(0x1b),
+            b'x' => {
+                let hex = self
+                    .input
+                    .get(self.pos..self.pos + 2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| self.error("invalid \\x escape"))?;
+                self.pos += 2;
+                Node::Byte(hex)
+            }
+            b if b.is_ascii_alphanumeric() => {
+                return Err(self.error("unknown escape"));
+            }
+            b => Node::Byte(b),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the leftmost match of `pattern` in `input`.
+    fn find(pattern: &str, input: &str) -> Option<String> {
+        let re = Regex::new(pattern).unwrap();
+        re.captures(input.as_bytes()).and_then(|c| c.get_str(0))
+    }
+
+    /// Returns the groups captured by the leftmost match.
+    fn groups(pattern: &str, input: &str) -> Vec<Option<String>> {
+        let re = Regex::new(pattern).unwrap();
+        let captures = re.captures(input.as_bytes()).unwrap();
+        (0..captures.len()).map(|i| captures.get_str(i)).collect()
+    }
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn literals_and_classes() {
+        for (pattern, input, expected) in &[
+            ("abc", "xxabcxx", Some("abc")),
+            ("abc", "ab", None),
+            ("", "abc", Some("")),
+            ("a.c", "abc", Some("abc")),
+            ("a.c", "a\nc", None),
+            ("[a-c]+", "xxcabz", Some("cab")),
+            ("[^0-9]+", "12ab3", Some("ab")),
+            ("[]a]+", "x]a]", Some("]a]")),
+            ("[a-]+", "-a-", Some("-a-")),
+            ("[\\d.]+", "v1.25 ", Some("1.25")),
+            ("\\d+", "abc 123", Some("123")),
+            ("\\w+", "  foo_1 ", Some("foo_1")),
+            ("\\s+", "a \t\r\nb", Some(" \t\r\n")),
+            ("\\D\\W\\S", "1a!x", Some("a!x")),
+            ("\\e\\[\\d*m", "x\x1b[0my", Some("\x1b[0m")),
+            ("\\x41\\.", "A.", Some("A.")),
+            ("a{", "a{", Some("a{")),
+            ("a{x}", "a{x}", Some("a{x}")),
+        ] {
+            assert_eq!(
+                find(pattern, input),
+                expected.map(String::from),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(find("^b", "ab\nbc"), some("b"));
+        assert_eq!(find("^a", "ba"), None);
+        assert_eq!(find("b$", "ab\r\nc"), some("b"));
+        assert_eq!(find("^$", "a\n\nb"), some(""));
+        assert_eq!(find("^\\$ $", "out\n$ "), some("$ "));
+        assert_eq!(
+            Regex::new("^x").unwrap().captures(b"ab\nx").unwrap().end(),
+            4
+        );
+    }
+
+    #[test]
+    fn alternation_prefers_earlier_alternatives() {
+        assert_eq!(find("a|ab", "ab"), some("a"));
+        assert_eq!(find("ab|a", "ab"), some("ab"));
+        assert_eq!(find("cat|dog", "hotdog cat"), some("dog"));
+        assert_eq!(find("(?:|x)y", "xy"), some("xy"));
+        assert_eq!(find("x(a|b|c)z", "xbz"), some("xbz"));
+    }
+
+    #[test]
+    fn repetition() {
+        for (pattern, input, expected) in &[
+            ("ab*", "abbbc", Some("abbb")),
+            ("ab*?", "abbbc", Some("a")),
+            ("ab+", "ac abb", Some("abb")),
+            ("ab+?", "abbb", Some("ab")),
+            ("ab?c", "ac", Some("ac")),
+            ("ab??", "ab", Some("a")),
+            ("a{3}", "aaaa", Some("aaa")),
+            ("a{2,}", "a aaaa", Some("aaaa")),
+            ("a{1,2}", "aaa", Some("aa")),
+            ("a{1,2}?", "aaa", Some("a")),
+            ("a{0}b", "ab", Some("b")),
+            ("<.*>", "<a><b>", Some("<a><b>")),
+            ("<.*?>", "<a><b>", Some("<a>")),
+            ("(?:ab)+", "xababa", Some("abab")),
+            ("(a*)*b", "aab", Some("aab")),
+            ("(a|)*b", "aab", Some("aab")),
+            ("(?:a*)+$", "aaa", Some("aaa")),
+        ] {
+            assert_eq!(
+                find(pattern, input),
+                expected.map(String::from),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn captures() {
+        assert_eq!(
+            groups("(\\w+)=(\\d+)", "set x=42;"),
+            vec![some("x=42"), some("x"), some("42")]
+        );
+        // Groups that didn't take part in the match capture nothing.
+        assert_eq!(groups("(a)|(b)", "b"), vec![some("b"), None, some("b")]);
+        // Repeated groups capture their last repetition.
+        assert_eq!(groups("(a|b)+", "abba"), vec![some("abba"), some("a")]);
+        assert_eq!(groups("(?:(a)|b)+", "ab"), vec![some("ab"), some("a")]);
+        assert_eq!(
+            groups("((a)(b)?)c", "ac"),
+            vec![some("ac"), some("a"), some("a"), None]
+        );
+        // Lazy groups stop as early as they can.
+        assert_eq!(
+            groups("(.*?)(\\d*)$", "ab12"),
+            vec![some("ab12"), some("ab"), some("12")]
+        );
+    }
+
+    #[test]
+    fn end_of_match() {
+        let re = Regex::new("\\$ ").unwrap();
+        let captures = re.captures(b"login: $ rest").unwrap();
+        assert_eq!(captures.end(), 9);
+        assert!(re.is_match(b"$ "));
+        assert!(!re.is_match(b"$"));
+    }
+
+    #[test]
+    fn long_input() {
+        // Repetition neither recurses nor backtracks per byte.
+        let input = "ab".repeat(50_000) + "c";
+        let re = Regex::new("(ab)*c").unwrap();
+        let captures = re.captures(input.as_bytes()).unwrap();
+        assert_eq!(captures.end(), input.len());
+        assert_eq!(captures.get(1), Some(&b"ab"[..]));
+
+        let re = Regex::new("(a|aa)*b").unwrap();
+        assert!(!re.is_match("a".repeat(100_000).as_bytes()));
+        let re = Regex::new("(x+x+)+y").unwrap();
+        assert!(!re.is_match("x".repeat(10_000).as_bytes()));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in &[
+            "(",
+            "(a",
+            "a)",
+            "[a",
+            "[b-a]",
+            "*",
+            "a**",
+            "+a",
+            "^*",
+            "\\",
+            "\\q",
+            "\\x4",
+            "a{3,2}",
+            "[\\D]",
+            "(?:a{100}){200}",
+            "a{1001}",
+            "a{1,1001}",
+            "a{99999999999}",
+            "a{99999999999999999999999}",
+            "a{2,99999999999999999999999}",
+        ] {
+            assert!(Regex::new(pattern).is_err(), "{}", pattern);
+        }
+        assert_eq!(
+            Regex::new("ab(").unwrap_err().to_string(),
+            "invalid pattern at offset 3: missing ')'"
+        );
+        assert_eq!(
+            Regex::new("a{99999999999}").unwrap_err().to_string(),
+            "invalid pattern at offset 1: repetition count too large"
+        );
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = |depth: usize| "(".repeat(depth) + "a" + &")".repeat(depth);
+        assert!(Regex::new(&nested(MAX_DEPTH)).is_ok());
+        assert!(Regex::new(&nested(MAX_DEPTH + 1)).is_err());
+        // Deep enough to overflow the stack without a limit.
+        assert_eq!(
+            Regex::new(&"(".repeat(100_000)).unwrap_err().to_string(),
+            "invalid pattern at offset 250: groups nested too deeply"
+        );
+    }
+}
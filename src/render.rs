@@ -0,0 +1,650 @@
+//! Rendering of emulated screens to images, as SVG or as PNG, and of
+//! recorded sessions to animated GIFs.
+//!
+//! Colors are those of xterm, with its 256 color palette, and true colors
+//! are kept as they are, except in GIFs, which are limited to the palette.
+//! PNG and GIF images are drawn with a bundled bitmap font that covers
+//! ASCII, box drawing and block characters. SVG images leave the glyphs to
+//! the viewer's monospace font, so they cover everything the font does.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::asciicast::{self, Cast};
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::gif::{GifWriter, Rect};
+use crate::png;
+use crate::screen::{Cell, Color, Screen};
+
+/// A color as red, green and blue.
+pub type Rgb = [u8; 3];
+
+/// The color of text in the default color.
+pub const DEFAULT_FG: Rgb = [0xe5, 0xe5, 0xe5];
+
+/// The color of the background in the default color.
+pub const DEFAULT_BG: Rgb = [0x00, 0x00, 0x00];
+
+/// The 16 ANSI colors, as xterm shows them.
+const ANSI_COLORS: [Rgb; 16] = [
+    [0x00, 0x00, 0x00],
+    [0xcd, 0x00, 0x00],
+    [0x00, 0xcd, 0x00],
+    [0xcd, 0xcd, 0x00],
+    [0x00, 0x00, 0xee],
+    [0xcd, 0x00, 0xcd],
+    [0x00, 0xcd, 0xcd],
+    [0xe5, 0xe5, 0xe5],
+    [0x7f, 0x7f, 0x7f],
+    [0xff, 0x00, 0x00],
+    [0x00, 0xff, 0x00],
+    [0xff, 0xff, 0x00],
+    [0x5c, 0x5c, 0xff],
+    [0xff, 0x00, 0xff],
+    [0x00, 0xff, 0xff],
+    [0xff, 0xff, 0xff],
+];
+
+/// Size of a cell in PNG images, in pixels before scaling. Glyphs get a
+/// column of space on either side and a row above and below.
+const CELL_WIDTH: usize = GLYPH_WIDTH + 2;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 3;
+
+/// Largest scale images can be drawn at.
+pub const MAX_SCALE: usize = 16;
+/// Most pixels an image drawn from a screen can have, so that a huge screen
+/// fails to be drawn rather than running out of memory.
+const MAX_PIXELS: usize = 1 << 26;
+
+/// Size of a cell in SVG images, and the size of the font filling it.
+const SVG_CELL_WIDTH: usize = 9;
+const SVG_CELL_HEIGHT: usize = 18;
+const SVG_FONT_SIZE: usize = 15;
+const SVG_FONT_FAMILY: &str = "'DejaVu Sans Mono', Menlo, Consolas, monospace";
+
+/// Returns the color `index` of xterm's 256 color palette.
+pub fn palette(index: u8) -> Rgb {
+    match index {
+        0..=15 => ANSI_COLORS[index as usize],
+        // A 6x6x6 color cube.
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = index - 16;
+            [level(n / 36), level(n / 6 % 6), level(n % 6)]
+        }
+        // A ramp of grays, leaving out black and white.
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            [gray, gray, gray]
+        }
+    }
+}
+
+/// Returns the colors a cell is drawn with, its text and its background,
+/// with its attributes and the cursor taken into account.
+fn cell_colors(cell: &Cell, cursor: bool) -> (Rgb, Rgb) {
+    let mut fg = match cell.fg {
+        Color::Default => DEFAULT_FG,
+        // Bold text is drawn in the bright variants of the ANSI colors.
+        Color::Indexed(index) if index < 8 && cell.attrs.bold => palette(index + 8),
+        Color::Indexed(index) => palette(index),
+        Color::Rgb(r, g, b) => [r, g, b],
+    };
+    let mut bg = match cell.bg {
+        Color::Default => DEFAULT_BG,
+        Color::Indexed(index) => palette(index),
+        Color::Rgb(r, g, b) => [r, g, b],
+    };
+
+    if cell.attrs.dim {
+        for (fg, bg) in fg.iter_mut().zip(bg.iter()) {
+            *fg = ((u16::from(*fg) + u16::from(*bg)) / 2) as u8;
+        }
+    }
+    if cell.attrs.inverse != cursor {
+        std::mem::swap(&mut fg, &mut bg);
+    }
+    if cell.attrs.hidden {
+        fg = bg;
+    }
+    (fg, bg)
+}
+
+/// Whether the cursor is shown at `row` and `col`.
+/// Returns the width and height in pixels of an image of `rows` by `cols`
+/// cells drawn at `scale`. Fails if it would have more than `MAX_PIXELS`.
+pub fn image_size(rows: u16, cols: u16, scale: usize) -> Result<(usize, usize), Box<dyn Error>> {
+    let pixels = |cells: u16, cell: usize| {
+        (cells as usize)
+            .checked_mul(cell)?
+            .checked_mul(scale.max(1))
+    };
+    match (pixels(cols, CELL_WIDTH), pixels(rows, CELL_HEIGHT)) {
+        (Some(width), Some(height))
+            if width.checked_mul(height).is_some_and(|n| n <= MAX_PIXELS) =>
+        {
+            Ok((width, height))
+        }
+        _ => Err("screen is too big to draw an image of".into()),
+    }
+}
+
+fn is_cursor(screen: &Screen, row: u16, col: u16) -> bool {
+    let cursor = screen.cursor();
+    cursor.visible && cursor.row == row && cursor.col == col
+}
+
+/// An image, made up of RGB pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// The pixels, row by row.
+    pub pixels: Vec<Rgb>,
+}
+
+impl Image {
+    /// Returns an image of `width` by `height` pixels of color `fill`.
+    pub fn new(width: usize, height: usize, fill: Rgb) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+
+    /// Draws `screen` with the bundled bitmap font, with every pixel of the
+    /// font blown up to `scale` by `scale` pixels. Fails if that makes for
+    /// too big an image.
+    pub fn from_screen(screen: &Screen, scale: usize) -> Result<Image, Box<dyn Error>> {
+        let scale = scale.max(1);
+        let (rows, cols) = screen.size();
+        let (width, height) = image_size(rows, cols, scale)?;
+        let mut image = Image::new(width, height, DEFAULT_BG);
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = match screen.cell(row, col) {
+                    Some(cell) => cell,
+                    None => continue,
+                };
+                let (fg, bg) = cell_colors(cell, is_cursor(screen, row, col));
+                let mut canvas = Canvas {
+                    image: &mut image,
+                    x: col as usize * CELL_WIDTH,
+                    y: row as usize * CELL_HEIGHT,
+                    scale,
+                    color: bg,
+                };
+                canvas.fill(0, 0, CELL_WIDTH, CELL_HEIGHT);
+                canvas.color = fg;
+                canvas.draw_cell(cell);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Writes the image to `w` as PNG.
+    pub fn write_png(&self, mut w: impl Write) -> io::Result<()> {
+        let rgb: Vec<u8> = self.pixels.iter().flatten().copied().collect();
+        png::write(&mut w, self.width, self.height, &rgb)
+    }
+}
+
+/// Draws in a single cell of an image, in unscaled pixels relative to the
+/// top left corner of the cell.
+struct Canvas<'a> {
+    image: &'a mut Image,
+    x: usize,
+    y: usize,
+    scale: usize,
+    color: Rgb,
+}
+
+impl Canvas<'_> {
+    /// Fills the rectangle of `width` by `height` pixels at `x` and `y`.
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let (x, y) = ((self.x + x) * self.scale, (self.y + y) * self.scale);
+        let x_end = (x + width * self.scale).min(self.image.width);
+        let y_end = (y + height * self.scale).min(self.image.height);
+        for y in y..y_end {
+            let row = y * self.image.width;
+            for pixel in &mut self.image.pixels[row + x.min(x_end)..row + x_end] {
+                *pixel = self.color;
+            }
+        }
+    }
+
+    fn dot(&mut self, x: usize, y: usize) {
+        if x < CELL_WIDTH && y < CELL_HEIGHT {
+            self.fill(x, y, 1, 1);
+        }
+    }
+
+    fn draw_cell(&mut self, cell: &Cell) {
+        if !self.draw_graphic(cell.c) {
+            let glyph = font::glyph(cell.c).unwrap_or(&MISSING_GLYPH);
+            for (y, bits) in glyph.iter().enumerate() {
+                // Italics lean the upper half of the glyph to the right.
+                let x = 1 + (cell.attrs.italic && y < GLYPH_HEIGHT / 2) as usize;
+                for bit in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> bit) != 0 {
+                        self.dot(x + bit, y + 1);
+                        // Bold is drawn by doubling every pixel sideways.
+                        if cell.attrs.bold {
+                            self.dot(x + bit + 1, y + 1);
+                        }
+                    }
+                }
+            }
+        }
+
+        if cell.attrs.underline {
+            self.fill(0, GLYPH_HEIGHT - 1, CELL_WIDTH, 1);
+        }
+        if cell.attrs.strikethrough {
+            self.fill(0, GLYPH_HEIGHT / 2 + 1, CELL_WIDTH, 1);
+        }
+    }
+
+    /// Draws `c` if it is a box drawing or block character. Returns whether
+    /// it was.
+    fn draw_graphic(&mut self, c: char) -> bool {
+        let (mid_x, mid_y) = (CELL_WIDTH / 2, CELL_HEIGHT / 2);
+        let (half_x, half_y) = (CELL_WIDTH / 2, CELL_HEIGHT / 2);
+        match c {
+            '█' => self.fill(0, 0, CELL_WIDTH, CELL_HEIGHT),
+            '▀' => self.fill(0, 0, CELL_WIDTH, half_y),
+            '▄' => self.fill(0, half_y, CELL_WIDTH, CELL_HEIGHT - half_y),
+            '▌' => self.fill(0, 0, half_x, CELL_HEIGHT),
+            '▐' => self.fill(half_x, 0, CELL_WIDTH - half_x, CELL_HEIGHT),
+            // Shades, as one in four, two in four and three in four pixels.
+            '░' | '▒' | '▓' => {
+                for y in 0..CELL_HEIGHT {
+                    for x in 0..CELL_WIDTH {
+                        let on = match c {
+                            '░' => x % 2 == 0 && y % 2 == 0,
+                            '▒' => (x + y) % 2 == 0,
+                            _ => x % 2 == 0 || y % 2 == 0,
+                        };
+                        if on {
+                            self.dot(x, y);
+                        }
+                    }
+                }
+            }
+            _ => {
+                let (up, down, left, right) = match box_lines(c) {
+                    Some(lines) => lines,
+                    None => return false,
+                };
+                if up {
+                    self.fill(mid_x, 0, 1, mid_y + 1);
+                }
+                if down {
+                    self.fill(mid_x, mid_y, 1, CELL_HEIGHT - mid_y);
+                }
+                if left {
+                    self.fill(0, mid_y, mid_x + 1, 1);
+                }
+                if right {
+                    self.fill(mid_x, mid_y, CELL_WIDTH - mid_x, 1);
+                }
+            }
+        }
+        true
+    }
+}
+
+/// The glyph of characters the font doesn't have, a box.
+const MISSING_GLYPH: [u8; GLYPH_HEIGHT] = [0x1f, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1f, 0x00, 0x00];
+
+/// Returns which of the lines up, down, left and right from the middle of
+/// the cell the box drawing character `c` is made of. Heavy, double and
+/// rounded lines are all drawn as light ones.
+fn box_lines(c: char) -> Option<(bool, bool, bool, bool)> {
+    let lines = match c {
+        '─' | '━' | '═' => (false, false, true, true),
+        '│' | '┃' | '║' => (true, true, false, false),
+        '┌' | '┏' | '╔' | '╭' => (false, true, false, true),
+        '┐' | '┓' | '╗' | '╮' => (false, true, true, false),
+        '└' | '┗' | '╚' | '╰' => (true, false, false, true),
+        '┘' | '┛' | '╝' | '╯' => (true, false, true, false),
+        '├' | '┣' | '╠' => (true, true, false, true),
+        '┤' | '┫' | '╣' => (true, true, true, false),
+        '┬' | '┳' | '╦' => (false, true, true, true),
+        '┴' | '┻' | '╩' => (true, false, true, true),
+        '┼' | '╋' | '╬' => (true, true, true, true),
+        '╴' => (false, false, true, false),
+        '╵' => (true, false, false, false),
+        '╶' => (false, false, false, true),
+        '╷' => (false, true, false, false),
+        _ => return None,
+    };
+    Some(lines)
+}
+
+/// How a recorded session is rendered to an animated GIF.
+#[derive(Debug, Clone)]
+pub struct GifOptions {
+    /// Most frames per second, 10 by default. Frames are only added when
+    /// the screen changes, so idle stretches take up a single frame.
+    pub fps: f64,
+    /// Upper bound, in seconds, on the pause between two events.
+    pub idle_limit: Option<f64>,
+    /// How many pixels each pixel of the font takes up, across and down.
+    pub scale: usize,
+}
+
+impl Default for GifOptions {
+    fn default() -> GifOptions {
+        GifOptions {
+            fps: 10.0,
+            idle_limit: None,
+            scale: 1,
+        }
+    }
+}
+
+/// How long the last frame of a GIF is shown before it starts over, in
+/// hundredths of a second.
+const GIF_END_DELAY: u16 = 100;
+
+/// Renders the output of `cast` to an animated GIF, written to `w`.
+///
+/// The GIF is as big as the biggest the screen of the session ever was.
+/// Screens that were smaller are drawn in its top left corner.
+pub fn write_gif(cast: &Cast, w: impl Write, opts: &GifOptions) -> Result<(), Box<dyn Error>> {
+    if opts.fps.is_nan() || opts.fps <= 0.0 {
+        return Err("frames per second must be positive".into());
+    }
+    let scale = opts.scale.max(1);
+    let events: Vec<_> = cast
+        .events
+        .iter()
+        .filter(|e| e.code == "o" || e.code == "r")
+        .collect();
+
+    // Find how big the screen gets, before making one that big.
+    let (mut rows, mut cols) = (cast.height.max(1), cast.width.max(1));
+    for event in events.iter().filter(|e| e.code == "r") {
+        if let Some((event_cols, event_rows)) = asciicast::parse_size(&event.data) {
+            rows = rows.max(event_rows);
+            cols = cols.max(event_cols);
+        }
+    }
+    let (width, height) = image_size(rows, cols, scale)
+        .ok()
+        .filter(|&(width, height)| {
+            width <= usize::from(u16::MAX) && height <= usize::from(u16::MAX)
+        })
+        .ok_or("screen is too big for a GIF")?;
+
+    let mut palette_rgb = [[0u8; 3]; 256];
+    for (index, color) in palette_rgb.iter_mut().enumerate() {
+        *color = palette(index as u8);
+    }
+    let mut frames = Frames {
+        gif: GifWriter::new(w, width as u16, height as u16, &palette_rgb)?,
+        width,
+        height,
+        colors: HashMap::new(),
+        shown: None,
+        pending: None,
+    };
+
+    // Frames are taken at ticks of 1/fps seconds, and show the screen with
+    // every event up to the tick.
+    let mut screen = Screen::new(cast.height, cast.width);
+    let mut tick = 0;
+    let (mut time, mut prev_time) = (0.0, 0.0);
+    for event in events {
+        let mut pause = (event.time - prev_time).max(0.0);
+        if let Some(limit) = opts.idle_limit {
+            pause = pause.min(limit);
+        }
+        prev_time = event.time;
+        time += pause;
+
+        let event_tick = (time * opts.fps).ceil() as u64;
+        if event_tick > tick {
+            frames.add(&Image::from_screen(&screen, scale)?, tick, opts.fps)?;
+            tick = event_tick;
+        }
+
+        match event.code.as_str() {
+            "o" => screen.process(event.data.as_bytes()),
+            _ => {
+                if let Some((cols, rows)) = asciicast::parse_size(&event.data) {
+                    screen.resize(rows, cols);
+                }
+            }
+        }
+    }
+    frames.add(&Image::from_screen(&screen, scale)?, tick, opts.fps)?;
+    frames.finish()?;
+    Ok(())
+}
+
+/// The frames of a GIF being written.
+struct Frames<W: Write> {
+    gif: GifWriter<W>,
+    width: usize,
+    height: usize,
+    /// Palette indices of the colors seen so far.
+    colors: HashMap<Rgb, u8>,
+    /// The palette indices of the pixels shown by the frames so far.
+    shown: Option<Vec<u8>>,
+    /// The last frame, the part of it that changed and the tick it is shown
+    /// at. It is written once it is known how long it is shown for.
+    pending: Option<(Rect, Vec<u8>, u64)>,
+}
+
+impl<W: Write> Frames<W> {
+    /// Adds a frame showing `image` at `tick`, unless it shows nothing new.
+    fn add(&mut self, image: &Image, tick: u64, fps: f64) -> io::Result<()> {
+        let mut indices = vec![0u8; self.width * self.height];
+        for y in 0..image.height.min(self.height) {
+            for x in 0..image.width.min(self.width) {
+                let color = image.pixels[y * image.width + x];
+                let index = *self
+                    .colors
+                    .entry(color)
+                    .or_insert_with(|| nearest_index(color));
+                indices[y * self.width + x] = index;
+            }
+        }
+
+        // Only the part of the image that changed needs to be drawn.
+        let (mut left, mut top, mut right, mut bottom) = (self.width, self.height, 0, 0);
+        match self.shown {
+            Some(ref shown) => {
+                for (i, (old, new)) in shown.iter().zip(&indices).enumerate() {
+                    if old != new {
+                        let (x, y) = (i % self.width, i / self.width);
+                        left = left.min(x);
+                        right = right.max(x + 1);
+                        top = top.min(y);
+                        bottom = bottom.max(y + 1);
+                    }
+                }
+            }
+            None => {
+                left = 0;
+                top = 0;
+                right = self.width;
+                bottom = self.height;
+            }
+        }
+        if left >= right {
+            return Ok(());
+        }
+
+        let mut changed = Vec::with_capacity((right - left) * (bottom - top));
+        for y in top..bottom {
+            changed.extend_from_slice(&indices[y * self.width + left..y * self.width + right]);
+        }
+        let rect = Rect {
+            left: left as u16,
+            top: top as u16,
+            width: (right - left) as u16,
+            height: (bottom - top) as u16,
+        };
+
+        if let Some((rect, changed, shown_at)) = self.pending.take() {
+            // Delays are rounded as times, so the rounding doesn't add up.
+            let centis = |tick: u64| (tick as f64 * 100.0 / fps).round() as u64;
+            let delay = (centis(tick) - centis(shown_at)).min(u64::from(u16::MAX));
+            self.gif.frame(&rect, &changed, delay as u16)?;
+        }
+        self.pending = Some((rect, changed, tick));
+        self.shown = Some(indices);
+        Ok(())
+    }
+
+    /// Writes the last frame and ends the GIF.
+    fn finish(mut self) -> io::Result<W> {
+        if let Some((rect, changed, _)) = self.pending.take() {
+            self.gif.frame(&rect, &changed, GIF_END_DELAY)?;
+        }
+        self.gif.finish()
+    }
+}
+
+/// Returns the index of the color of xterm's 256 color palette that is
+/// closest to `color`.
+fn nearest_index(color: Rgb) -> u8 {
+    let distance = |other: Rgb| -> u32 {
+        color
+            .iter()
+            .zip(other.iter())
+            .map(|(&a, &b)| (i32::from(a) - i32::from(b)).pow(2) as u32)
+            .sum()
+    };
+    (0..=255u8)
+        .min_by_key(|&index| distance(palette(index)))
+        .unwrap_or(0)
+}
+
+fn hex(color: Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Appends `c` to `out`, escaped for XML.
+fn push_xml(out: &mut String, c: char) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        c => out.push(c),
+    }
+}
+
+/// Returns `screen` as an SVG image.
+pub fn svg(screen: &Screen) -> String {
+    let (rows, cols) = screen.size();
+    let width = cols as usize * SVG_CELL_WIDTH;
+    let height = rows as usize * SVG_CELL_HEIGHT;
+
+    let mut out = String::new();
+    // Writing to a string can't fail.
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+         viewBox=\"0 0 {w} {h}\">",
+        w = width,
+        h = height
+    );
+    let _ = writeln!(
+        out,
+        "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+        hex(DEFAULT_BG)
+    );
+    let _ = writeln!(
+        out,
+        "<g font-family=\"{}\" font-size=\"{}\" xml:space=\"preserve\">",
+        SVG_FONT_FAMILY, SVG_FONT_SIZE
+    );
+
+    for row in 0..rows {
+        let cells: Vec<(&Cell, Rgb, Rgb)> = (0..cols)
+            .filter_map(|col| {
+                let cell = screen.cell(row, col)?;
+                let (fg, bg) = cell_colors(cell, is_cursor(screen, row, col));
+                Some((cell, fg, bg))
+            })
+            .collect();
+        let y = row as usize * SVG_CELL_HEIGHT;
+
+        // Backgrounds, as runs of cells of the same color.
+        let mut start = 0;
+        while start < cells.len() {
+            let bg = cells[start].2;
+            let len = cells[start..].iter().take_while(|c| c.2 == bg).count();
+            if bg != DEFAULT_BG {
+                let _ = writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+                    start * SVG_CELL_WIDTH,
+                    y,
+                    len * SVG_CELL_WIDTH,
+                    SVG_CELL_HEIGHT,
+                    hex(bg)
+                );
+            }
+            start += len;
+        }
+
+        // Text, as runs of cells that are drawn alike. Each run is
+        // stretched to fill its cells exactly, whatever the font.
+        let mut start = 0;
+        while start < cells.len() {
+            let (cell, fg, _) = cells[start];
+            let len = cells[start..]
+                .iter()
+                .take_while(|c| c.1 == fg && c.0.attrs == cell.attrs)
+                .count();
+            let mut run = &cells[start..start + len];
+            // Trailing blanks only show when they are decorated.
+            if !cell.attrs.underline && !cell.attrs.strikethrough {
+                while run.last().is_some_and(|c| c.0.c == ' ') {
+                    run = &run[..run.len() - 1];
+                }
+            }
+            if !run.is_empty() {
+                let _ = write!(
+                    out,
+                    "<text x=\"{}\" y=\"{}\" fill=\"{}\" textLength=\"{}\" \
+                     lengthAdjust=\"spacingAndGlyphs\"",
+                    start * SVG_CELL_WIDTH,
+                    y + SVG_FONT_SIZE - 1,
+                    hex(fg),
+                    run.len() * SVG_CELL_WIDTH
+                );
+                if cell.attrs.bold {
+                    out.push_str(" font-weight=\"bold\"");
+                }
+                if cell.attrs.italic {
+                    out.push_str(" font-style=\"italic\"");
+                }
+                match (cell.attrs.underline, cell.attrs.strikethrough) {
+                    (true, true) => out.push_str(" text-decoration=\"underline line-through\""),
+                    (true, false) => out.push_str(" text-decoration=\"underline\""),
+                    (false, true) => out.push_str(" text-decoration=\"line-through\""),
+                    (false, false) => {}
+                }
+                out.push('>');
+                for c in run {
+                    push_xml(&mut out, c.0.c);
+                }
+                out.push_str("</text>\n");
+            }
+            start += len;
+        }
+    }
+
+    out.push_str("</g>\n</svg>\n");
+    out
+}
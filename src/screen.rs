@@ -0,0 +1,990 @@
+//! A headless emulation of a VT100/xterm screen.
+//!
+//! `Screen` consumes the output of a program and keeps track of what a
+//! terminal would show: a grid of cells with their characters, colors and
+//! attributes, the cursor, the alternate screen and the scroll region.
+//! It understands the control sequences that full-screen programs commonly
+//! use, and ignores the rest. Every character takes up a single cell.
+//!
+//! Requests for the cursor position and the like are answered, and the
+//! answers can be collected with `take_responses` to be sent back to the
+//! program.
+
+use std::mem;
+
+use crate::vt::{Parser, Perform};
+
+/// The color of a cell's text or background.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Color {
+    /// The terminal's default color.
+    #[default]
+    Default,
+    /// A color of the 256 color palette. The first 16 are the standard and
+    /// bright ANSI colors.
+    Indexed(u8),
+    /// A true color.
+    Rgb(u8, u8, u8),
+}
+
+/// How the text of a cell is rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Attrs {
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub inverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+}
+
+/// A single character cell of the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: Attrs,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            c: ' ',
+            fg: Color::Default,
+            bg: Color::Default,
+            attrs: Attrs::default(),
+        }
+    }
+}
+
+/// The position and visibility of the cursor. Rows and columns count from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub row: u16,
+    pub col: u16,
+    pub visible: bool,
+}
+
+/// The emulated screen of a terminal.
+#[derive(Debug, Clone)]
+pub struct Screen {
+    parser: Parser,
+    state: State,
+}
+
+impl Screen {
+    /// Returns a blank screen of `rows` by `cols` cells.
+    pub fn new(rows: u16, cols: u16) -> Screen {
+        Screen {
+            parser: Parser::new(),
+            state: State::new(rows.max(1) as usize, cols.max(1) as usize),
+        }
+    }
+
+    /// Updates the screen with the output of a program.
+    pub fn process(&mut self, data: &[u8]) {
+        self.parser.advance(&mut self.state, data);
+    }
+
+    /// The size of the screen, in rows and columns.
+    pub fn size(&self) -> (u16, u16) {
+        (self.state.rows as u16, self.state.cols as u16)
+    }
+
+    /// Changes the size of the screen to `rows` by `cols` cells, keeping
+    /// what fits of the contents.
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.state
+            .resize(rows.max(1) as usize, cols.max(1) as usize);
+    }
+
+    /// Returns the cell at `row` and `col`, if they are on the screen.
+    pub fn cell(&self, row: u16, col: u16) -> Option<&Cell> {
+        self.state
+            .grid
+            .get(row as usize)
+            .and_then(|line| line.get(col as usize))
+    }
+
+    /// The cursor.
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            row: self.state.row as u16,
+            col: self.state.col as u16,
+            visible: self.state.cursor_visible,
+        }
+    }
+
+    /// Returns the text of `row`, without trailing whitespace.
+    pub fn row_text(&self, row: u16) -> String {
+        match self.state.grid.get(row as usize) {
+            Some(line) => {
+                let text: String = line.iter().map(|cell| cell.c).collect();
+                text.trim_end().to_string()
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Returns the text on the screen, a line per row, without trailing
+    /// whitespace and trailing blank rows.
+    pub fn contents(&self) -> String {
+        let rows: Vec<String> = (0..self.state.rows as u16)
+            .map(|row| self.row_text(row))
+            .collect();
+        rows.join("\n").trim_end().to_string()
+    }
+
+    /// Whether the alternate screen, used by full-screen programs, is shown.
+    pub fn is_alternate(&self) -> bool {
+        self.state.alternate
+    }
+
+    /// The window title last set by the program.
+    pub fn title(&self) -> &str {
+        &self.state.title
+    }
+
+    /// Returns the answers to requests of the program, such as for the
+    /// cursor position, to be written back to it.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        mem::take(&mut self.state.responses)
+    }
+}
+
+/// Character sets that can be designated as G0 and G1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Charset {
+    Ascii,
+    /// The DEC special graphics set, which has line drawing characters.
+    LineDrawing,
+}
+
+/// What is saved by DECSC and restored by DECRC.
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    row: usize,
+    col: usize,
+    pen: Cell,
+    origin_mode: bool,
+    charsets: [Charset; 2],
+    shifted: bool,
+}
+
+/// The state of the terminal, which the parser acts on.
+#[derive(Debug, Clone)]
+struct State {
+    rows: usize,
+    cols: usize,
+    grid: Vec<Vec<Cell>>,
+    // The screen not shown: the primary one while the alternate one is.
+    other_grid: Vec<Vec<Cell>>,
+    alternate: bool,
+    row: usize,
+    col: usize,
+    // Set after printing in the last column, the next character goes on
+    // the next line.
+    wrap_pending: bool,
+    cursor_visible: bool,
+    // The attributes and colors new text is written with. Its `c` is unused.
+    pen: Cell,
+    saved: Option<SavedCursor>,
+    // The scroll region, inclusive.
+    scroll_top: usize,
+    scroll_bottom: usize,
+    tabs: Vec<bool>,
+    autowrap: bool,
+    origin_mode: bool,
+    insert_mode: bool,
+    charsets: [Charset; 2],
+    // Whether G1 is the active character set, rather than G0.
+    shifted: bool,
+    last_char: Option<char>,
+    title: String,
+    responses: Vec<u8>,
+}
+
+impl State {
+    fn new(rows: usize, cols: usize) -> State {
+        State {
+            rows,
+            cols,
+            grid: vec![vec![Cell::default(); cols]; rows],
+            other_grid: vec![vec![Cell::default(); cols]; rows],
+            alternate: false,
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            cursor_visible: true,
+            pen: Cell::default(),
+            saved: None,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            tabs: (0..cols).map(|col| col % 8 == 0).collect(),
+            autowrap: true,
+            origin_mode: false,
+            insert_mode: false,
+            charsets: [Charset::Ascii; 2],
+            shifted: false,
+            last_char: None,
+            title: String::new(),
+            responses: Vec::new(),
+        }
+    }
+
+    fn resize(&mut self, rows: usize, cols: usize) {
+        for grid in [&mut self.grid, &mut self.other_grid].iter_mut() {
+            grid.resize(rows, vec![Cell::default(); cols]);
+            for line in grid.iter_mut() {
+                line.resize(cols, Cell::default());
+            }
+        }
+        self.tabs.resize(cols, false);
+        for col in (self.cols..cols).filter(|col| col % 8 == 0) {
+            self.tabs[col] = true;
+        }
+        self.rows = rows;
+        self.cols = cols;
+        self.row = self.row.min(rows - 1);
+        self.col = self.col.min(cols - 1);
+        self.wrap_pending = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+    }
+
+    /// A blank cell, in the current background color.
+    fn blank(&self) -> Cell {
+        Cell {
+            bg: self.pen.bg,
+            ..Cell::default()
+        }
+    }
+
+    fn blank_line(&self) -> Vec<Cell> {
+        vec![self.blank(); self.cols]
+    }
+
+    /// Moves the cursor, keeping it on the screen.
+    fn goto(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols - 1);
+        self.wrap_pending = false;
+    }
+
+    /// Moves the cursor to `row` as CUP and VPA count it, relative to the
+    /// scroll region in origin mode.
+    fn goto_row(&mut self, row: usize, col: usize) {
+        if self.origin_mode {
+            let row = (self.scroll_top + row).min(self.scroll_bottom);
+            self.goto(row, col);
+        } else {
+            self.goto(row, col);
+        }
+    }
+
+    /// The rows the cursor can move between vertically: the scroll region if
+    /// it is in there, otherwise the whole screen.
+    fn vertical_bounds(&self) -> (usize, usize) {
+        if self.row >= self.scroll_top && self.row <= self.scroll_bottom {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.rows - 1)
+        }
+    }
+
+    /// Scrolls the scroll region up by `n` lines, adding blank lines at the
+    /// bottom.
+    fn scroll_up(&mut self, n: usize) {
+        let n = n.min(self.scroll_bottom - self.scroll_top + 1);
+        let blank = self.blank_line();
+        self.grid.drain(self.scroll_top..self.scroll_top + n);
+        let at = self.scroll_bottom + 1 - n;
+        self.grid.splice(at..at, std::iter::repeat_n(blank, n));
+    }
+
+    /// Scrolls the scroll region down by `n` lines, adding blank lines at
+    /// the top.
+    fn scroll_down(&mut self, n: usize) {
+        let n = n.min(self.scroll_bottom - self.scroll_top + 1);
+        let blank = self.blank_line();
+        self.grid
+            .drain(self.scroll_bottom + 1 - n..=self.scroll_bottom);
+        self.grid.splice(
+            self.scroll_top..self.scroll_top,
+            std::iter::repeat_n(blank, n),
+        );
+    }
+
+    fn linefeed(&mut self) {
+        if self.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.row < self.rows - 1 {
+            self.row += 1;
+        }
+        self.wrap_pending = false;
+    }
+
+    fn reverse_index(&mut self) {
+        if self.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.row > 0 {
+            self.row -= 1;
+        }
+        self.wrap_pending = false;
+    }
+
+    /// Erases the cells of the current row from `start` up to `end`.
+    fn erase_cells(&mut self, start: usize, end: usize) {
+        let blank = self.blank();
+        let end = end.min(self.cols);
+        if start < end {
+            self.grid[self.row][start..end]
+                .iter_mut()
+                .for_each(|cell| *cell = blank);
+        }
+    }
+
+    /// Erases the rows from `start` up to `end`.
+    fn erase_rows(&mut self, start: usize, end: usize) {
+        let blank = self.blank_line();
+        for line in &mut self.grid[start..end.min(self.rows)] {
+            *line = blank.clone();
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved = Some(SavedCursor {
+            row: self.row,
+            col: self.col,
+            pen: self.pen,
+            origin_mode: self.origin_mode,
+            charsets: self.charsets,
+            shifted: self.shifted,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved.unwrap_or(SavedCursor {
+            row: 0,
+            col: 0,
+            pen: Cell::default(),
+            origin_mode: false,
+            charsets: [Charset::Ascii; 2],
+            shifted: false,
+        });
+        self.origin_mode = saved.origin_mode;
+        // The scroll region may have changed since, the cursor can't leave
+        // it in origin mode.
+        let row = if self.origin_mode {
+            saved.row.clamp(self.scroll_top, self.scroll_bottom)
+        } else {
+            saved.row
+        };
+        self.goto(row, saved.col);
+        self.pen = saved.pen;
+        self.charsets = saved.charsets;
+        self.shifted = saved.shifted;
+    }
+
+    /// Switches between the primary and the alternate screen.
+    fn set_alternate(&mut self, alternate: bool) {
+        if alternate != self.alternate {
+            mem::swap(&mut self.grid, &mut self.other_grid);
+            self.alternate = alternate;
+        }
+    }
+
+    fn set_mode(&mut self, params: &[u16], private: bool, on: bool) {
+        for &mode in params {
+            match (private, mode) {
+                (false, 4) => self.insert_mode = on,
+                (true, 6) => {
+                    self.origin_mode = on;
+                    self.goto_row(0, 0);
+                }
+                (true, 7) => self.autowrap = on,
+                (true, 25) => self.cursor_visible = on,
+                (true, 47) | (true, 1047) => {
+                    if on {
+                        self.set_alternate(true);
+                    } else {
+                        // Leaving the alternate screen clears it for next time.
+                        if self.alternate {
+                            self.erase_rows(0, self.rows);
+                        }
+                        self.set_alternate(false);
+                    }
+                }
+                (true, 1048) => {
+                    if on {
+                        self.save_cursor();
+                    } else {
+                        self.restore_cursor();
+                    }
+                }
+                (true, 1049) => {
+                    if on {
+                        self.save_cursor();
+                        self.set_alternate(true);
+                        self.erase_rows(0, self.rows);
+                    } else {
+                        self.set_alternate(false);
+                        self.restore_cursor();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Selects the graphic rendition: the attributes and colors of text.
+    fn sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.pen = Cell::default();
+            return;
+        }
+
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            let attrs = &mut self.pen.attrs;
+            match param {
+                0 => self.pen = Cell::default(),
+                1 => attrs.bold = true,
+                2 => attrs.dim = true,
+                3 => attrs.italic = true,
+                4 => attrs.underline = true,
+                5 | 6 => attrs.blink = true,
+                7 => attrs.inverse = true,
+                8 => attrs.hidden = true,
+                9 => attrs.strikethrough = true,
+                21 | 22 => {
+                    attrs.bold = false;
+                    attrs.dim = false;
+                }
+                23 => attrs.italic = false,
+                24 => attrs.underline = false,
+                25 => attrs.blink = false,
+                27 => attrs.inverse = false,
+                28 => attrs.hidden = false,
+                29 => attrs.strikethrough = false,
+                30..=37 => self.pen.fg = Color::Indexed((param - 30) as u8),
+                38 => self.pen.fg = extended_color(&mut params).unwrap_or(self.pen.fg),
+                39 => self.pen.fg = Color::Default,
+                40..=47 => self.pen.bg = Color::Indexed((param - 40) as u8),
+                48 => self.pen.bg = extended_color(&mut params).unwrap_or(self.pen.bg),
+                49 => self.pen.bg = Color::Default,
+                90..=97 => self.pen.fg = Color::Indexed((param - 90 + 8) as u8),
+                100..=107 => self.pen.bg = Color::Indexed((param - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+
+    /// Answers a device status report.
+    fn report(&mut self, param: u16) {
+        match param {
+            // Status: OK.
+            5 => self.responses.extend_from_slice(b"\x1b[0n"),
+            // Cursor position.
+            6 => {
+                let row = if self.origin_mode {
+                    self.row.saturating_sub(self.scroll_top)
+                } else {
+                    self.row
+                };
+                let report = format!("\x1b[{};{}R", row + 1, self.col + 1);
+                self.responses.extend_from_slice(report.as_bytes());
+            }
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        let title = mem::take(&mut self.title);
+        let responses = mem::take(&mut self.responses);
+        *self = State::new(self.rows, self.cols);
+        self.title = title;
+        self.responses = responses;
+    }
+}
+
+/// Reads the color of SGR 38 and 48 from `params`: `5;<index>` or
+/// `2;<r>;<g>;<b>`.
+fn extended_color(params: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    match params.next()? {
+        5 => Some(Color::Indexed(params.next()?.min(255) as u8)),
+        2 => {
+            let mut component = || params.next().map(|c| c.min(255) as u8);
+            Some(Color::Rgb(component()?, component()?, component()?))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the character the DEC special graphics set has in place of `c`.
+fn line_drawing(c: char) -> char {
+    match c {
+        '`' => '◆',
+        'a' => '▒',
+        'f' => '°',
+        'g' => '±',
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'o' => '⎺',
+        'p' => '⎻',
+        'q' => '─',
+        'r' => '⎼',
+        's' => '⎽',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'y' => '≤',
+        'z' => '≥',
+        '{' => 'π',
+        '|' => '≠',
+        '}' => '£',
+        '~' => '·',
+        c => c,
+    }
+}
+
+impl Perform for State {
+    fn print(&mut self, c: char) {
+        let c = match self.charsets[self.shifted as usize] {
+            Charset::LineDrawing => line_drawing(c),
+            Charset::Ascii => c,
+        };
+
+        if self.wrap_pending && self.autowrap {
+            self.col = 0;
+            self.linefeed();
+        }
+
+        let line = &mut self.grid[self.row];
+        if self.insert_mode {
+            line.pop();
+            line.insert(self.col, Cell::default());
+        }
+        line[self.col] = Cell { c, ..self.pen };
+
+        if self.col + 1 < self.cols {
+            self.col += 1;
+            self.wrap_pending = false;
+        } else {
+            self.wrap_pending = true;
+        }
+        self.last_char = Some(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\x08' => {
+                self.col = self.col.saturating_sub(1);
+                self.wrap_pending = false;
+            }
+            b'\t' => {
+                let next = (self.col + 1..self.cols).find(|&col| self.tabs[col]);
+                self.col = next.unwrap_or(self.cols - 1);
+                self.wrap_pending = false;
+            }
+            b'\n' | b'\x0b' | b'\x0c' => self.linefeed(),
+            b'\r' => {
+                self.col = 0;
+                self.wrap_pending = false;
+            }
+            // Shift out and in, to G1 and G0.
+            b'\x0e' => self.shifted = true,
+            b'\x0f' => self.shifted = false,
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &[u16], intermediates: &[u8], action: u8) {
+        let param = |i: usize| params.get(i).copied().unwrap_or(0) as usize;
+        // Most parameters are counts or positions, where 0 means 1.
+        let count = |i: usize| param(i).max(1);
+
+        match (intermediates, action) {
+            ([], b'@') => {
+                let blank = self.blank();
+                let line = &mut self.grid[self.row];
+                for _ in 0..count(0).min(self.cols - self.col) {
+                    line.pop();
+                    line.insert(self.col, blank);
+                }
+            }
+            ([], b'A') => {
+                let (top, _) = self.vertical_bounds();
+                let row = self.row.saturating_sub(count(0)).max(top);
+                self.goto(row, self.col);
+            }
+            ([], b'B') | ([], b'e') => {
+                let (_, bottom) = self.vertical_bounds();
+                let row = (self.row + count(0)).min(bottom);
+                self.goto(row, self.col);
+            }
+            ([], b'C') | ([], b'a') => self.goto(self.row, self.col + count(0)),
+            ([], b'D') => self.goto(self.row, self.col.saturating_sub(count(0))),
+            ([], b'E') => {
+                let (_, bottom) = self.vertical_bounds();
+                self.goto((self.row + count(0)).min(bottom), 0);
+            }
+            ([], b'F') => {
+                let (top, _) = self.vertical_bounds();
+                self.goto(self.row.saturating_sub(count(0)).max(top), 0);
+            }
+            ([], b'G') | ([], b'`') => self.goto(self.row, count(0) - 1),
+            ([], b'H') | ([], b'f') => self.goto_row(count(0) - 1, count(1) - 1),
+            ([], b'I') => {
+                for _ in 0..count(0) {
+                    self.execute(b'\t');
+                }
+            }
+            ([], b'J') => match param(0) {
+                0 => {
+                    self.erase_cells(self.col, self.cols);
+                    self.erase_rows(self.row + 1, self.rows);
+                }
+                1 => {
+                    self.erase_cells(0, self.col + 1);
+                    self.erase_rows(0, self.row);
+                }
+                2 => self.erase_rows(0, self.rows),
+                _ => {}
+            },
+            ([], b'K') => match param(0) {
+                0 => self.erase_cells(self.col, self.cols),
+                1 => self.erase_cells(0, self.col + 1),
+                2 => self.erase_cells(0, self.cols),
+                _ => {}
+            },
+            ([], b'L') | ([], b'M') => {
+                if self.row < self.scroll_top || self.row > self.scroll_bottom {
+                    return;
+                }
+                // Insert or delete lines by scrolling the part of the scroll
+                // region from the cursor down.
+                let top = mem::replace(&mut self.scroll_top, self.row);
+                if action == b'L' {
+                    self.scroll_down(count(0));
+                } else {
+                    self.scroll_up(count(0));
+                }
+                self.scroll_top = top;
+                self.goto(self.row, 0);
+            }
+            ([], b'P') => {
+                let blank = self.blank();
+                let line = &mut self.grid[self.row];
+                for _ in 0..count(0).min(self.cols - self.col) {
+                    line.remove(self.col);
+                    line.push(blank);
+                }
+            }
+            ([], b'S') => self.scroll_up(count(0)),
+            ([], b'T') => self.scroll_down(count(0)),
+            ([], b'X') => self.erase_cells(self.col, self.col + count(0)),
+            ([], b'Z') => {
+                for _ in 0..count(0) {
+                    let prev = (0..self.col).rev().find(|&col| self.tabs[col]);
+                    self.col = prev.unwrap_or(0);
+                }
+                self.wrap_pending = false;
+            }
+            ([], b'b') => {
+                if let Some(c) = self.last_char {
+                    for _ in 0..count(0) {
+                        self.print(c);
+                    }
+                }
+            }
+            ([], b'c') => self.responses.extend_from_slice(b"\x1b[?1;2c"),
+            ([], b'd') => self.goto_row(count(0) - 1, self.col),
+            ([], b'g') => match param(0) {
+                0 => self.tabs[self.col] = false,
+                3 => self.tabs.iter_mut().for_each(|tab| *tab = false),
+                _ => {}
+            },
+            ([], b'h') => self.set_mode(params, false, true),
+            ([], b'l') => self.set_mode(params, false, false),
+            ([b'?'], b'h') => self.set_mode(params, true, true),
+            ([b'?'], b'l') => self.set_mode(params, true, false),
+            ([], b'm') => self.sgr(params),
+            ([], b'n') => self.report(param(0) as u16),
+            ([], b'r') => {
+                let top = count(0) - 1;
+                let bottom = if param(1) == 0 { self.rows } else { param(1) };
+                if top < bottom.min(self.rows) - 1 {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom.min(self.rows) - 1;
+                    self.goto_row(0, 0);
+                }
+            }
+            ([], b's') => self.save_cursor(),
+            ([], b'u') => self.restore_cursor(),
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+        match (intermediates, byte) {
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            ([], b'D') => self.linefeed(),
+            ([], b'E') => {
+                self.col = 0;
+                self.linefeed();
+            }
+            ([], b'H') => self.tabs[self.col] = true,
+            ([], b'M') => self.reverse_index(),
+            ([], b'c') => self.reset(),
+            ([b'('], b'0') => self.charsets[0] = Charset::LineDrawing,
+            ([b')'], b'0') => self.charsets[1] = Charset::LineDrawing,
+            ([b'('], _) => self.charsets[0] = Charset::Ascii,
+            ([b')'], _) => self.charsets[1] = Charset::Ascii,
+            // Fills the screen with E's, for aligning the screen.
+            ([b'#'], b'8') => {
+                let e = Cell {
+                    c: 'E',
+                    ..Cell::default()
+                };
+                self.grid = vec![vec![e; self.cols]; self.rows];
+            }
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, data: &[u8]) {
+        // Setting the icon name and window title, or just the title.
+        if let Some(title) = data
+            .strip_prefix(b"0;")
+            .or_else(|| data.strip_prefix(b"2;"))
+        {
+            self.title = String::from_utf8_lossy(title).into_owned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(rows: u16, cols: u16, data: &[u8]) -> Screen {
+        let mut screen = Screen::new(rows, cols);
+        screen.process(data);
+        screen
+    }
+
+    fn cursor(screen: &Screen) -> (u16, u16) {
+        let cursor = screen.cursor();
+        (cursor.row, cursor.col)
+    }
+
+    /// Returns the rows of the screen, with trailing whitespace but not
+    /// trailing blank rows trimmed.
+    fn rows(screen: &Screen) -> Vec<String> {
+        (0..screen.size().0)
+            .map(|row| screen.row_text(row))
+            .collect()
+    }
+
+    #[test]
+    fn cursor_movement() {
+        for (data, expected) in &[
+            (&b"\x1b[3;5H"[..], (2, 4)),
+            (b"\x1b[H", (0, 0)),
+            (b"\x1b[;5f", (0, 4)),
+            (b"\x1b[99;99H", (9, 19)),
+            (b"\x1b[5;5H\x1b[2A", (2, 4)),
+            (b"\x1b[5;5H\x1b[A", (3, 4)),
+            (b"\x1b[5;5H\x1b[9A", (0, 4)),
+            (b"\x1b[5;5H\x1b[3B", (7, 4)),
+            (b"\x1b[5;5H\x1b[99B", (9, 4)),
+            (b"\x1b[5;5H\x1b[3C", (4, 7)),
+            (b"\x1b[5;5H\x1b[99C", (4, 19)),
+            (b"\x1b[5;5H\x1b[3D", (4, 1)),
+            (b"\x1b[5;5H\x1b[99D", (4, 0)),
+            (b"\x1b[5;5H\x1b[2E", (6, 0)),
+            (b"\x1b[5;5H\x1b[2F", (2, 0)),
+            (b"\x1b[5;5H\x1b[12G", (4, 11)),
+            (b"\x1b[5;5H\x1b[8d", (7, 4)),
+            (b"abc\x08\x08", (0, 1)),
+            (b"\x08", (0, 0)),
+            (b"abc\r", (0, 0)),
+            (b"a\tb\t", (0, 16)),
+            (b"\x1b[3I", (0, 19)),
+            (b"\x1b[15G\x1b[Z", (0, 8)),
+            (b"a\nb", (1, 2)),
+        ] {
+            assert_eq!(cursor(&screen(10, 20, data)), *expected, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn wrapping() {
+        let s = screen(3, 5, b"abcde");
+        assert_eq!(cursor(&s), (0, 4));
+        let s = screen(3, 5, b"abcdef");
+        assert_eq!(rows(&s), ["abcde", "f", ""]);
+        let s = screen(3, 5, b"\x1b[?7labcdefg");
+        assert_eq!(rows(&s), ["abcdg", "", ""]);
+        let s = screen(2, 5, b"1\r\n2\r\n3");
+        assert_eq!(rows(&s), ["2", "3"]);
+    }
+
+    #[test]
+    fn scroll_regions() {
+        let lines = b"1\r\n2\r\n3\r\n4\r\n5";
+        // Line feeds at the bottom of the region scroll only the region.
+        let mut s = screen(5, 5, lines);
+        s.process(b"\x1b[2;4r\x1b[4;1H\nx");
+        assert_eq!(rows(&s), ["1", "3", "4", "x", "5"]);
+
+        // Reverse index at the top of the region scrolls it down.
+        let mut s = screen(5, 5, lines);
+        s.process(b"\x1b[2;4r\x1b[2;1H\x1bMx");
+        assert_eq!(rows(&s), ["1", "x", "2", "3", "5"]);
+
+        // Scrolling up and down.
+        let mut s = screen(5, 5, lines);
+        s.process(b"\x1b[2;4r\x1b[S");
+        assert_eq!(rows(&s), ["1", "3", "4", "", "5"]);
+        s.process(b"\x1b[2T");
+        assert_eq!(rows(&s), ["1", "", "", "3", "5"]);
+
+        // Inserting and deleting lines within the region.
+        let mut s = screen(5, 5, lines);
+        s.process(b"\x1b[2;4r\x1b[3;3H\x1b[L");
+        assert_eq!(rows(&s), ["1", "2", "", "3", "5"]);
+        assert_eq!(cursor(&s), (2, 0));
+        s.process(b"\x1b[2M");
+        assert_eq!(rows(&s), ["1", "2", "", "", "5"]);
+
+        // Vertical movement stops at the margins, from inside the region.
+        let mut s = screen(5, 5, b"\x1b[2;4r\x1b[3;1H");
+        s.process(b"\x1b[9A");
+        assert_eq!(cursor(&s), (1, 0));
+        s.process(b"\x1b[9B");
+        assert_eq!(cursor(&s), (3, 0));
+        s.process(b"\x1b[5;1H\x1b[9A");
+        assert_eq!(cursor(&s), (0, 0));
+
+        // Invalid regions are ignored, and resizing resets the region.
+        let mut s = screen(5, 5, b"\x1b[4;2r\x1b[5;1H\n");
+        assert_eq!(cursor(&s), (4, 0));
+        s.process(b"\x1b[1;2r");
+        s.resize(6, 5);
+        s.process(b"\x1b[6;1H\nx");
+        assert_eq!(s.row_text(5), "x");
+    }
+
+    #[test]
+    fn origin_mode() {
+        let mut s = screen(10, 20, b"\x1b[3;6r\x1b[?6h");
+        assert_eq!(cursor(&s), (2, 0));
+        s.process(b"\x1b[2;4H");
+        assert_eq!(cursor(&s), (3, 3));
+        s.process(b"\x1b[9;1H");
+        assert_eq!(cursor(&s), (5, 0));
+        s.process(b"\x1b[2d");
+        assert_eq!(cursor(&s), (3, 0));
+        s.process(b"\x1b[6n");
+        assert_eq!(s.take_responses(), b"\x1b[2;1R");
+
+        // Leaving origin mode homes the cursor to the top of the screen.
+        s.process(b"\x1b[?6l");
+        assert_eq!(cursor(&s), (0, 0));
+        s.process(b"\x1b[2;4H");
+        assert_eq!(cursor(&s), (1, 3));
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let mut s = screen(10, 20, b"\x1b[3;4H\x1b[1;31m\x1b(0\x1b7");
+        s.process(b"\x1b[H\x1b[m\x1b(B\x1b8q");
+        assert_eq!(cursor(&s), (2, 4));
+        let cell = s.cell(2, 3).unwrap();
+        assert_eq!(cell.c, '─');
+        assert_eq!(cell.fg, Color::Indexed(1));
+        assert!(cell.attrs.bold);
+
+        // SCOSC and SCORC, and restoring without saving first.
+        let s = screen(10, 20, b"\x1b[5;6H\x1b[s\x1b[H\x1b[u");
+        assert_eq!(cursor(&s), (4, 5));
+        let mut s = screen(10, 20, b"\x1b[5;6H\x1b8");
+        assert_eq!(cursor(&s), (0, 0));
+
+        // Origin mode is restored too, and keeps the cursor in the region
+        // even if it changed since.
+        s.process(b"\x1b[?6h\x1b7\x1b[5;10r\x1b8");
+        assert_eq!(cursor(&s), (4, 0));
+        s.process(b"\x1b[6n");
+        assert_eq!(s.take_responses(), b"\x1b[1;1R");
+        s.process(b"\x1b[?6l\x1b[20;1H\x1b7\x1b[?6h\x1b8");
+        assert_eq!(cursor(&s), (9, 0));
+
+        // The alternate screen saves the cursor, and comes back cleared.
+        let mut s = screen(3, 10, b"main\x1b[?1049h");
+        assert!(s.is_alternate());
+        assert_eq!(s.contents(), "");
+        s.process(b"\x1b[2;2Halt\x1b[?1049l");
+        assert!(!s.is_alternate());
+        assert_eq!(s.contents(), "main");
+        assert_eq!(cursor(&s), (0, 4));
+        s.process(b"\x1b[?1049h");
+        assert_eq!(s.contents(), "");
+    }
+
+    #[test]
+    fn device_status_reports() {
+        let mut s = screen(10, 20, b"\x1b[5n\x1b[4;7H\x1b[6n\x1b[c\x1b[99n");
+        assert_eq!(s.take_responses(), b"\x1b[0n\x1b[4;7R\x1b[?1;2c");
+        assert!(s.take_responses().is_empty());
+    }
+
+    #[test]
+    fn erasing_and_editing() {
+        let text = b"abcdef\r\nghijkl\r\nmnopqr";
+        let mut s = screen(3, 6, text);
+        s.process(b"\x1b[2;3H\x1b[K");
+        assert_eq!(rows(&s), ["abcdef", "gh", "mnopqr"]);
+        s.process(b"\x1b[1K");
+        assert_eq!(rows(&s), ["abcdef", "", "mnopqr"]);
+        let mut s = screen(3, 6, text);
+        s.process(b"\x1b[2;3H\x1b[J");
+        assert_eq!(rows(&s), ["abcdef", "gh", ""]);
+        let mut s = screen(3, 6, text);
+        s.process(b"\x1b[2;3H\x1b[1J");
+        assert_eq!(rows(&s), ["", "   jkl", "mnopqr"]);
+        s.process(b"\x1b[2J");
+        assert_eq!(s.contents(), "");
+
+        let mut s = screen(1, 6, b"abcdef\x1b[2G\x1b[2P");
+        assert_eq!(s.row_text(0), "adef");
+        s.process(b"\x1b[2@");
+        assert_eq!(s.row_text(0), "a  def");
+        s.process(b"\x1b[3X");
+        assert_eq!(s.row_text(0), "a   ef");
+        s.process(b"\x1b[4hxy");
+        assert_eq!(s.row_text(0), "axy");
+    }
+
+    #[test]
+    fn title_and_reset() {
+        let mut s = screen(3, 10, b"\x1b]2;hello\x07text\x1b[?25l");
+        assert_eq!(s.title(), "hello");
+        assert!(!s.cursor().visible);
+        s.process(b"\x1bc");
+        assert_eq!(s.contents(), "");
+        assert!(s.cursor().visible);
+        assert_eq!(s.title(), "hello");
+    }
+}
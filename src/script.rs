@@ -0,0 +1,267 @@
+//! Scripts that drive a program through an `Expect` session.
+//!
+//! A script has one command per line. Blank lines and lines starting with
+//! `#` are ignored. Arguments are separated by whitespace and can be quoted:
+//! single quotes take their contents literally, double quotes understand the
+//! escapes `\n \r \t \e \\ \" \xHH` and leave any other escape as it is, so
+//! patterns such as `"\d+"` can be written naturally.
+//!
+//! ```text
+//! timeout 5               # default timeout for the commands below, in seconds
+//! expect "login: "        # wait for output to match a pattern
+//! sendline "root"         # send a line of input, ending it with a carriage return
+//! expect 'Password: ' 30  # wait with a specific timeout
+//! send "hunter2\r"        # send input as it is
+//! sleep 0.5
+//! expect_eof              # wait for the program to close the terminal
+//! ```
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::expect::Expect;
+use crate::regex::Regex;
+
+/// Timeout of expectations, unless the script sets another one.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest duration a script may give, a day.
+const MAX_SECS: f64 = 24.0 * 60.0 * 60.0;
+
+/// A command of a script.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Sends input to the program.
+    Send(Vec<u8>),
+    /// Waits for output matching the pattern, with an optional timeout
+    /// overriding the default one.
+    Expect(Regex, Option<Duration>),
+    /// Waits for the program to close the terminal.
+    ExpectEof(Option<Duration>),
+    Sleep(Duration),
+    /// Sets the default timeout of the following commands.
+    Timeout(Duration),
+}
+
+/// A parsed script.
+#[derive(Debug, Clone)]
+pub struct Script {
+    /// Commands with the line numbers they are on.
+    commands: Vec<(usize, Command)>,
+}
+
+impl Script {
+    /// Reads and parses the script at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Script, Box<dyn Error>> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+        Script::parse(&src).map_err(|err| format!("{}:{}", path.display(), err).into())
+    }
+
+    /// Parses the script `src`.
+    pub fn parse(src: &str) -> Result<Script, Box<dyn Error>> {
+        let mut commands = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let lineno = i + 1;
+            let command = parse_line(line).map_err(|err| format!("{}: {}", lineno, err))?;
+            if let Some(command) = command {
+                commands.push((lineno, command));
+            }
+        }
+        Ok(Script { commands })
+    }
+
+    /// Runs the script against `exp`, stopping at the first command that fails.
+    pub fn run(&self, exp: &mut Expect) -> Result<(), Box<dyn Error>> {
+        let mut timeout = DEFAULT_TIMEOUT;
+        for (lineno, command) in &self.commands {
+            let res = match command {
+                Command::Send(data) => exp.send(data),
+                Command::Expect(re, t) => exp
+                    .expect(re, Some(t.unwrap_or(timeout)))
+                    .map(|_| ())
+                    .map_err(|err| format!("{} waiting for /{}/", err, re).into()),
+                Command::ExpectEof(t) => exp
+                    .expect_eof(Some(t.unwrap_or(timeout)))
+                    .map(|_| ())
+                    .map_err(|err| format!("{} waiting for end of output", err).into()),
+                Command::Sleep(d) => {
+                    thread::sleep(*d);
+                    Ok(())
+                }
+                Command::Timeout(t) => {
+                    timeout = *t;
+                    Ok(())
+                }
+            };
+            res.map_err(|err| format!("line {}: {}", lineno, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a line of a script, returning `None` for blank lines and comments.
+fn parse_line(line: &str) -> Result<Option<Command>, String> {
+    let words = split_words(line)?;
+    let (name, args) = match words.split_first() {
+        Some((name, args)) => (String::from_utf8_lossy(name).into_owned(), args),
+        None => return Ok(None),
+    };
+
+    let arg = |i: usize| -> Result<&[u8], String> {
+        args.get(i)
+            .map(Vec::as_slice)
+            .ok_or_else(|| format!("{} requires an argument", name))
+    };
+    let secs = |i: usize| -> Result<Option<Duration>, String> {
+        match args.get(i) {
+            None => Ok(None),
+            Some(secs) => std::str::from_utf8(secs)
+                .ok()
+                .and_then(|secs| secs.parse::<f64>().ok())
+                .filter(|secs| (0.0..=MAX_SECS).contains(secs))
+                .map(|secs| Some(Duration::from_secs_f64(secs)))
+                .ok_or_else(|| format!("invalid number of seconds for {}", name)),
+        }
+    };
+    let max_args = |n: usize| -> Result<(), String> {
+        if args.len() > n {
+            Err(format!("too many arguments for {}", name))
+        } else {
+            Ok(())
+        }
+    };
+
+    let command = match name.as_str() {
+        "send" => {
+            max_args(1)?;
+            Command::Send(arg(0)?.to_vec())
+        }
+        "sendline" => {
+            max_args(1)?;
+            let mut data = arg(0)?.to_vec();
+            data.push(b'\r');
+            Command::Send(data)
+        }
+        "expect" => {
+            max_args(2)?;
+            let pattern = String::from_utf8(arg(0)?.to_vec())
+                .map_err(|_| "pattern is not valid UTF-8".to_string())?;
+            let re = Regex::new(&pattern).map_err(|err| err.to_string())?;
+            Command::Expect(re, secs(1)?)
+        }
+        "expect_eof" => {
+            max_args(1)?;
+            Command::ExpectEof(secs(0)?)
+        }
+        "sleep" => {
+            max_args(1)?;
+            arg(0)?;
+            Command::Sleep(secs(0)?.unwrap_or_default())
+        }
+        "timeout" => {
+            max_args(1)?;
+            arg(0)?;
+            Command::Timeout(secs(0)?.unwrap_or_default())
+        }
+        _ => return Err(format!("unknown command: {}", name)),
+    };
+    Ok(Some(command))
+}
+
+/// Splits `line` into words, handling quotes and escapes and dropping
+/// comments.
+fn split_words(line: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.bytes().peekable();
+
+    loop {
+        while chars.peek().is_some_and(u8::is_ascii_whitespace) {
+            chars.next();
+        }
+        let first = match chars.peek() {
+            None | Some(b'#') => return Ok(words),
+            Some(&b) => b,
+        };
+
+        let mut word = Vec::new();
+        match first {
+            b'\'' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some(b'\'') => break,
+                        Some(b) => word.push(b),
+                        None => return Err("missing closing '".to_string()),
+                    }
+                }
+            }
+            b'"' => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some(b'"') => break,
+                        Some(b'\\') => match chars.next() {
+                            Some(b'n') => word.push(b'\n'),
+                            Some(b'r') => word.push(b'\r'),
+                            Some(b't') => word.push(b'\t'),
+                            Some(b'e') => word.push(0x1b),
+                            Some(b'\\') => word.push(b'\\'),
+                            Some(b'"') => word.push(b'"'),
+                            Some(b'x') => {
+                                let hex: Vec<u8> = (0..2).filter_map(|_| chars.next()).collect();
+                                let byte = std::str::from_utf8(&hex)
+                                    .ok()
+                                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                                    .ok_or("invalid \\x escape")?;
+                                word.push(byte);
+                            }
+                            Some(b) => word.extend_from_slice(&[b'\\', b]),
+                            None => return Err("missing closing \"".to_string()),
+                        },
+                        Some(b) => word.push(b),
+                        None => return Err("missing closing \"".to_string()),
+                    }
+                }
+            }
+            _ => {
+                while let Some(&b) = chars.peek() {
+                    if b.is_ascii_whitespace() {
+                        break;
+                    }
+                    word.push(b);
+                    chars.next();
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let script = Script::parse("timeout 2.5\nexpect '\\$ ' 0\nsleep 86400\n").unwrap();
+        assert_eq!(script.commands.len(), 3);
+        for (src, line) in &[
+            ("timeout 1e30", 1),
+            ("send x\nexpect foo inf", 2),
+            ("expect foo NaN", 1),
+            ("sleep -1", 1),
+            ("\nexpect_eof 86401", 2),
+        ] {
+            let err = Script::parse(src).unwrap_err().to_string();
+            assert!(
+                err.starts_with(&format!("{}: invalid number of seconds", line)),
+                "{}",
+                err
+            );
+        }
+    }
+}
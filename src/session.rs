@@ -0,0 +1,701 @@
+//! Sessions that outlive the terminal they were started from.
+//!
+//! A session is a daemon that holds a PTY master and the child running on it,
+//! and serves them over a Unix domain socket. Clients attach to the socket
+//! to proxy between their terminal and the PTY, and can detach again while
+//! the child keeps running, like dtach.
+//!
+//! Any number of clients can be attached at once. The server writes
+//! everything the child outputs to every client verbatim. Clients send
+//! messages, each a one byte type, a two byte big-endian payload length and
+//! the payload. Which clients' input reaches the child depends on the
+//! session's `InputMode`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::error::Error;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::path::PathBuf;
+use std::process;
+use std::str::FromStr;
+
+use mio::event::Event;
+use mio::net::{UnixListener, UnixStream};
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg, OFlag};
+use nix::poll::{self, PollFd, PollFlags};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::socket::{self, MsgFlags};
+use nix::sys::termios::{self, SetArg, Termios};
+use nix::unistd::{self, ForkResult};
+
+use crate::proxy::NonBlocking;
+use crate::pty::{wait_child, PtyPair};
+use crate::signal::{self, read_signals, TERMINATION_SIGNALS};
+use crate::term::{self, TermSettings, Winsize};
+
+// Tokens of the server.
+const LISTENER: Token = Token(0);
+const PTY_MASTER: Token = Token(1);
+const SIGNAL: Token = Token(2);
+/// Clients get tokens from here on up, in the order they attach.
+const FIRST_CLIENT: Token = Token(3);
+
+// Tokens of the client.
+const STDIN: Token = Token(0);
+const SERVER: Token = Token(1);
+
+/// Message carrying input for the child.
+const MSG_DATA: u8 = 0;
+/// Message carrying the client's window size, as big-endian columns and rows.
+const MSG_RESIZE: u8 = 1;
+/// Message a client introduces itself with, carrying a byte of flags.
+const MSG_HELLO: u8 = 2;
+/// Flag for clients that only watch the session, and never send input.
+const HELLO_READ_ONLY: u8 = 1;
+
+/// Moves the cursor home and clears the screen.
+const CLEAR_SCREEN: &[u8] = b"\x1b[H\x1b[2J";
+
+/// Amount of recent output kept to replay to attaching clients, by default.
+pub const DEFAULT_SCROLLBACK: usize = 64 * 1024;
+/// Most recent output a session can be asked to keep.
+pub const MAX_SCROLLBACK: usize = 1 << 20;
+
+/// Output that may be waiting to be sent to a client before it is
+/// considered too slow to keep up, and disconnected. There is room for a
+/// whole replay of the scrollback, on top of the live output.
+const MAX_PENDING_OUTPUT: usize = MAX_SCROLLBACK + CLEAR_SCREEN.len() + (1 << 20);
+
+/// Returns the path of the socket for the session `name`.
+/// Sockets live in `$PTYME_DIR`, or a per-user directory in the system's
+/// temporary directory if it isn't set. The directory must belong to the
+/// current user and be private to them. Names can't contain '/' or start
+/// with '.', so they can't reach outside the directory or hide in it.
+pub fn socket_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(format!("invalid session name: {:?}", name).into());
+    }
+
+    let dir = match env::var_os("PTYME_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::temp_dir().join(format!("ptyme-{}", unistd::getuid())),
+    };
+    DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+
+    // The directory may have been there already, so make sure nobody else
+    // can get at the sockets in it.
+    let meta = fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()).into());
+    }
+    if meta.uid() != unistd::getuid().as_raw() {
+        return Err(format!("{} is not owned by the current user", dir.display()).into());
+    }
+    if meta.mode() & 0o7777 != 0o700 {
+        return Err(format!(
+            "{} has unsafe permissions {:o}, expected 700",
+            dir.display(),
+            meta.mode() & 0o7777
+        )
+        .into());
+    }
+
+    Ok(dir.join(name))
+}
+
+/// Settings of a session.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    /// Initial size of the PTY.
+    pub winsize: Winsize,
+    /// Which clients may send input.
+    pub input_mode: InputMode,
+    /// Number of bytes of recent output to replay to attaching clients.
+    pub scrollback: usize,
+    /// Terminal settings to start from, instead of the defaults of new PTYs.
+    pub termios: Option<Termios>,
+    /// Terminal settings for the command, on top of `termios`.
+    pub settings: TermSettings,
+}
+
+/// Starts the session `name` running `cmd` in a daemon process.
+/// Returns once the session is ready to be attached to.
+pub fn create(name: &str, cmd: &[String], opts: &SessionOptions) -> Result<(), Box<dyn Error>> {
+    let path = socket_path(name)?;
+    if path.exists() {
+        if StdUnixStream::connect(&path).is_ok() {
+            return Err(format!("session {} already exists", name).into());
+        }
+        // Nothing is listening, so the socket was left behind by a session
+        // that didn't get to clean up.
+        fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+
+    // The terminal may hang up as soon as we return, before the daemon got
+    // to detach from it, so it starts out ignoring hangups. Serving installs
+    // its own handler later on.
+    let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+    let hangup = unsafe { sigaction(Signal::SIGHUP, &ignore) }?;
+    let forked = unistd::fork();
+    if !matches!(forked, Ok(ForkResult::Child)) {
+        unsafe { sigaction(Signal::SIGHUP, &hangup) }?;
+        forked?;
+        return Ok(());
+    }
+
+    // Detach from the terminal of whoever started the session.
+    let status = unistd::setsid()
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| redirect_stdio())
+        .and_then(|_| serve(listener, cmd, opts));
+    let _ = fs::remove_file(&path);
+    process::exit(status.unwrap_or(1));
+}
+
+/// Points stdin, stdout and stderr at /dev/null.
+fn redirect_stdio() -> Result<(), Box<dyn Error>> {
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..3 {
+        unistd::dup2(null.as_raw_fd(), fd)?;
+    }
+    Ok(())
+}
+
+/// Which clients may send input to a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Every client that didn't attach read-only.
+    All,
+    /// Only the driver, the client that has been attached the longest of
+    /// those that didn't attach read-only. Everyone else is a viewer.
+    Driver,
+}
+
+impl FromStr for InputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InputMode, String> {
+        match s {
+            "all" => Ok(InputMode::All),
+            "driver" => Ok(InputMode::Driver),
+            _ => Err(format!("unknown input mode: {}", s)),
+        }
+    }
+}
+
+/// A bounded buffer of the most recent output of the child.
+struct Scrollback {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    /// Whether output has been dropped to stay within the capacity.
+    truncated: bool,
+}
+
+impl Scrollback {
+    /// The buffer grows as output arrives, up to `capacity` bytes.
+    fn new(capacity: usize) -> Scrollback {
+        Scrollback {
+            buf: VecDeque::new(),
+            capacity,
+            truncated: false,
+        }
+    }
+
+    /// Appends `output`, dropping the oldest output beyond the capacity.
+    fn push(&mut self, output: &[u8]) {
+        if output.len() > self.capacity {
+            self.truncated = true;
+        }
+        let output = &output[output.len().saturating_sub(self.capacity)..];
+        let excess = (self.buf.len() + output.len()).saturating_sub(self.capacity);
+        if excess > 0 {
+            self.buf.drain(..excess);
+            self.truncated = true;
+        }
+        self.buf.extend(output);
+    }
+
+    /// Returns the output to replay to a new client.
+    /// Once output was dropped, the oldest line is likely incomplete, along
+    /// with any escape sequence in it, so replay starts after it.
+    fn replay(&self) -> Vec<u8> {
+        let skip = if self.truncated {
+            self.buf
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(0, |i| i + 1)
+        } else {
+            0
+        };
+        self.buf.iter().skip(skip).copied().collect()
+    }
+}
+
+/// A client attached to the session.
+struct Client {
+    stream: UnixStream,
+    token: Token,
+    /// Whether the client asked to only watch the session.
+    read_only: bool,
+    /// Bytes received that don't make up a complete message yet.
+    input: Vec<u8>,
+    /// Output not yet sent, because the client wasn't ready for it.
+    output: Vec<u8>,
+}
+
+impl Client {
+    /// Sends as much pending output as the client will take, and
+    /// (re)registers interest in being able to send the rest.
+    fn flush(&mut self, poll: &Poll) -> io::Result<()> {
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(n) => {
+                    self.output.drain(..n);
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let interest = if self.output.is_empty() {
+            Interest::READABLE
+        } else {
+            Interest::READABLE | Interest::WRITABLE
+        };
+        poll.registry()
+            .reregister(&mut self.stream, self.token, interest)
+    }
+
+    /// Reads everything the client sent and returns the complete messages.
+    /// Returns `None` once the client has gone away.
+    fn read_messages(&mut self) -> Option<Vec<(u8, Vec<u8>)>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return None,
+                Ok(n) => self.input.extend_from_slice(&buf[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return None,
+            }
+        }
+
+        let mut messages = Vec::new();
+        while self.input.len() >= 3 {
+            let len = u16::from_be_bytes([self.input[1], self.input[2]]) as usize;
+            if self.input.len() < 3 + len {
+                break;
+            }
+            let msg: Vec<u8> = self.input.drain(..3 + len).collect();
+            messages.push((msg[0], msg[3..].to_vec()));
+        }
+        Some(messages)
+    }
+}
+
+/// The server side of a session.
+struct Server {
+    poll: Poll,
+    listener: UnixListener,
+    pty_pair: PtyPair,
+    input_mode: InputMode,
+    /// Attached clients, in the order they attached in, as tokens only
+    /// ever increase.
+    clients: BTreeMap<Token, Client>,
+    next_token: usize,
+    /// Input for the child that the PTY wasn't ready to take yet.
+    input: Vec<u8>,
+    scrollback: Scrollback,
+}
+
+impl Server {
+    /// Returns whether input and resizes from the client `token` are accepted.
+    fn accepts_input(&self, token: Token) -> bool {
+        match self.input_mode {
+            InputMode::All => self.clients.get(&token).is_some_and(|c| !c.read_only),
+            InputMode::Driver => {
+                self.clients
+                    .values()
+                    .find(|c| !c.read_only)
+                    .map(|c| c.token)
+                    == Some(token)
+            }
+        }
+    }
+
+    /// Accepts all pending connections.
+    fn accept(&mut self) -> io::Result<()> {
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            self.poll
+                .registry()
+                .register(&mut stream, token, Interest::READABLE)?;
+
+            // Catch the client up on recent output, on a clear screen.
+            let mut output = CLEAR_SCREEN.to_vec();
+            output.extend(self.scrollback.replay());
+            let mut client = Client {
+                stream,
+                token,
+                read_only: false,
+                input: Vec::new(),
+                output,
+            };
+            if client.flush(&self.poll).is_ok() {
+                self.clients.insert(token, client);
+            }
+        }
+    }
+
+    /// Sends `output` of the child to every client. Clients that can't keep
+    /// up are disconnected.
+    fn broadcast(&mut self, output: &[u8]) {
+        self.scrollback.push(output);
+        let poll = &self.poll;
+        self.clients.retain(|_, c| {
+            c.output.extend_from_slice(output);
+            c.output.len() <= MAX_PENDING_OUTPUT && c.flush(poll).is_ok()
+        });
+    }
+
+    /// Handles an event for the client `token`.
+    fn client_ready(&mut self, token: Token, event: &Event) -> Result<(), Box<dyn Error>> {
+        let c = match self.clients.get_mut(&token) {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        if event.is_writable() && c.flush(&self.poll).is_err() {
+            self.clients.remove(&token);
+            return Ok(());
+        }
+        if !event.is_readable() && !event.is_read_closed() {
+            return Ok(());
+        }
+        let messages = match c.read_messages() {
+            Some(messages) => messages,
+            None => {
+                self.clients.remove(&token);
+                return Ok(());
+            }
+        };
+
+        for (kind, payload) in messages {
+            match kind {
+                MSG_HELLO if payload.len() == 1 => {
+                    if let Some(c) = self.clients.get_mut(&token) {
+                        c.read_only = payload[0] & HELLO_READ_ONLY != 0;
+                    }
+                }
+                MSG_DATA if self.accepts_input(token) => {
+                    self.input.extend_from_slice(&payload);
+                    flush_input(
+                        &self.poll,
+                        self.pty_pair.master.as_raw_fd(),
+                        &mut self.input,
+                    )?;
+                }
+                MSG_RESIZE if payload.len() == 4 && self.accepts_input(token) => {
+                    let mut winsize = term::get_winsize(self.pty_pair.master.as_raw_fd())?;
+                    winsize.ws_col = u16::from_be_bytes([payload[0], payload[1]]);
+                    winsize.ws_row = u16::from_be_bytes([payload[2], payload[3]]);
+                    self.pty_pair.resize(&winsize)?;
+                }
+                // Ignore anything we don't understand, or don't accept.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `cmd` on a new PTY and serves it on `listener` until it exits.
+/// Returns the exit status of `cmd`.
+fn serve(
+    listener: UnixListener,
+    cmd: &[String],
+    opts: &SessionOptions,
+) -> Result<i32, Box<dyn Error>> {
+    let signals = signal::signal_pipe(&TERMINATION_SIGNALS)?;
+    let pty_pair = PtyPair::open()?;
+    pty_pair.resize(&opts.winsize)?;
+    if let Some(ref saved) = opts.termios {
+        termios::tcsetattr(pty_pair.master.as_raw_fd(), SetArg::TCSANOW, saved)?;
+    }
+    pty_pair.configure(&opts.settings)?;
+    let child = pty_pair.spawn(cmd)?;
+    let master = pty_pair.master.as_raw_fd();
+    fcntl::fcntl(master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+
+    let mut server = Server {
+        poll: Poll::new()?,
+        listener,
+        pty_pair,
+        input_mode: opts.input_mode,
+        clients: BTreeMap::new(),
+        next_token: FIRST_CLIENT.0,
+        input: Vec::new(),
+        scrollback: Scrollback::new(opts.scrollback),
+    };
+    let mut events = Events::with_capacity(128);
+    let registry = server.poll.registry();
+    registry.register(&mut server.listener, LISTENER, Interest::READABLE)?;
+    registry.register(&mut SourceFd(&master), PTY_MASTER, Interest::READABLE)?;
+    registry.register(&mut SourceFd(&signals), SIGNAL, Interest::READABLE)?;
+
+    let mut buf = [0u8; 4096];
+
+    loop {
+        match server.poll.poll(&mut events, None) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        }
+
+        for event in events.iter() {
+            match event.token() {
+                LISTENER => server.accept()?,
+                PTY_MASTER => {
+                    if event.is_writable() {
+                        flush_input(&server.poll, master, &mut server.input)?;
+                    }
+                    // Read everything there is, we won't be told again.
+                    loop {
+                        let n = match unistd::read(master, &mut buf) {
+                            // The child and everything else on the slave is gone.
+                            Ok(0) | Err(nix::Error::Sys(Errno::EIO)) => return wait_child(child),
+                            Ok(n) => n,
+                            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                            Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+                            Err(err) => return Err(err.into()),
+                        };
+                        server.broadcast(&buf[..n]);
+                    }
+                }
+                SIGNAL => {
+                    if let Some(sig) = read_signals(signals)?.into_iter().next() {
+                        return Ok(128 + sig as i32);
+                    }
+                }
+                token => server.client_ready(token, event)?,
+            }
+        }
+    }
+}
+
+/// Writes as much of the `pending` input to the non-blocking PTY `master`
+/// as it will take, and (re)registers interest in being able to write the rest.
+fn flush_input(poll: &Poll, master: RawFd, pending: &mut Vec<u8>) -> Result<(), Box<dyn Error>> {
+    while !pending.is_empty() {
+        match unistd::write(master, pending) {
+            Ok(n) => {
+                pending.drain(..n);
+            }
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let interest = if pending.is_empty() {
+        Interest::READABLE
+    } else {
+        Interest::READABLE | Interest::WRITABLE
+    };
+    poll.registry()
+        .reregister(&mut SourceFd(&master), PTY_MASTER, interest)?;
+    Ok(())
+}
+
+/// Encodes a message of the given `kind` for the server.
+fn message(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(3 + payload.len());
+    msg.push(kind);
+    msg.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    msg.extend_from_slice(payload);
+    msg
+}
+
+/// Encodes a message telling the server the size of the terminal `fd`.
+fn resize_message(fd: RawFd) -> Result<Vec<u8>, nix::Error> {
+    let winsize = term::get_winsize(fd)?;
+    let mut payload = winsize.ws_col.to_be_bytes().to_vec();
+    payload.extend_from_slice(&winsize.ws_row.to_be_bytes());
+    Ok(message(MSG_RESIZE, &payload))
+}
+
+/// Connects to the session `name`.
+pub fn connect(name: &str) -> Result<StdUnixStream, Box<dyn Error>> {
+    let path = socket_path(name)?;
+    StdUnixStream::connect(&path)
+        .map_err(|err| format!("cannot attach to session {}: {}", name, err).into())
+}
+
+/// Attaches the terminal `stdin` to the session connected to by `stream`,
+/// until either the session ends or `detach_key` is typed. If `read_only`
+/// is set, nothing typed other than `detach_key` is sent to the session. Window size changes and
+/// termination signals are read from the `signals` pipe.
+///
+/// Returns 0 on detach or when the session ends, and 128 + the signal
+/// number if a termination signal arrives.
+pub fn attach(
+    mut stream: StdUnixStream,
+    stdin: RawFd,
+    signals: RawFd,
+    detach_key: u8,
+    read_only: bool,
+) -> Result<i32, Box<dyn Error>> {
+    let sock = stream.as_raw_fd();
+
+    let mut poll = Poll::new()?;
+    let mut events = Events::with_capacity(128);
+    poll.registry()
+        .register(&mut SourceFd(&stdin), STDIN, Interest::READABLE)?;
+    poll.registry()
+        .register(&mut SourceFd(&sock), SERVER, Interest::READABLE)?;
+    poll.registry()
+        .register(&mut SourceFd(&signals), SIGNAL, Interest::READABLE)?;
+
+    let flags = if read_only { HELLO_READ_ONLY } else { 0 };
+    stream.write_all(&message(MSG_HELLO, &[flags]))?;
+    stream.write_all(&resize_message(stdin)?)?;
+
+    // Stdin is edge-triggered, so everything there is has to be read each
+    // time. Stdout likely shares the terminal's flags, and is written with
+    // `write_fd`, which copes with it being non-blocking too.
+    let _stdin_nonblocking = NonBlocking::new(stdin)?;
+    let stdout: RawFd = 1;
+    let mut buf = [0u8; 4096];
+
+    loop {
+        match poll.poll(&mut events, None) {
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => res?,
+        }
+
+        for event in events.iter() {
+            match event.token() {
+                STDIN => loop {
+                    let n = match unistd::read(stdin, &mut buf) {
+                        Ok(n) => n,
+                        Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                        Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+                        Err(err) => return Err(err.into()),
+                    };
+                    let input = &buf[..n];
+                    let detach = input.iter().position(|&b| b == detach_key);
+                    let input = if read_only {
+                        &[]
+                    } else {
+                        &input[..detach.unwrap_or(n)]
+                    };
+                    // Messages carry at most u16::MAX bytes.
+                    for chunk in input.chunks(u16::MAX as usize) {
+                        stream.write_all(&message(MSG_DATA, chunk))?;
+                    }
+                    if detach.is_some() {
+                        write_fd(stdout, b"\r\n[detached]\r\n")?;
+                        return Ok(0);
+                    }
+                    if n == 0 {
+                        return Ok(0);
+                    }
+                },
+                SERVER => loop {
+                    // Read everything there is without blocking, we won't be
+                    // told again. Writes to the server are left blocking.
+                    let n = match socket::recv(sock, &mut buf, MsgFlags::MSG_DONTWAIT) {
+                        Ok(0) => return Ok(0),
+                        Ok(n) => n,
+                        Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                        Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+                        Err(err) => return Err(err.into()),
+                    };
+                    write_fd(stdout, &buf[..n])?;
+                },
+                SIGNAL => {
+                    for sig in read_signals(signals)? {
+                        match sig {
+                            Signal::SIGWINCH => stream.write_all(&resize_message(stdin)?)?,
+                            sig => return Ok(128 + sig as i32),
+                        }
+                    }
+                }
+                // We don't expect any events with tokens other than those we provided.
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Writes all of `data` to `fd`, waiting for it to become writable whenever
+/// it is non-blocking and full.
+fn write_fd(fd: RawFd, mut data: &[u8]) -> Result<(), nix::Error> {
+    while !data.is_empty() {
+        match unistd::write(fd, data) {
+            Ok(n) => data = &data[n..],
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                poll::poll(&mut [PollFd::new(fd, PollFlags::POLLOUT)], -1)?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrollback() {
+        let mut scrollback = Scrollback::new(8);
+        assert_eq!(scrollback.replay(), b"");
+        scrollback.push(b"ab\ncd");
+        scrollback.push(b"ef");
+        assert_eq!(scrollback.replay(), b"ab\ncdef");
+        // Once output is dropped, the replay starts at the next line.
+        scrollback.push(b"\ngh");
+        assert_eq!(scrollback.replay(), b"cdef\ngh");
+        scrollback.push(b"ij");
+        assert_eq!(scrollback.replay(), b"ghij");
+    }
+
+    #[test]
+    fn scrollback_chunk_over_capacity() {
+        let mut scrollback = Scrollback::new(4);
+        scrollback.push(b"abcdef\ngh");
+        assert_eq!(scrollback.replay(), b"gh");
+
+        let mut scrollback = Scrollback::new(4);
+        scrollback.push(b"abcdefgh");
+        assert!(scrollback.truncated);
+        assert_eq!(scrollback.replay(), b"efgh");
+    }
+
+    #[test]
+    fn invalid_names() {
+        for name in &["", ".", "..", ".hidden", "a/b", "/tmp/x", "../x"] {
+            let err = socket_path(name).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid session name: {:?}", name));
+        }
+    }
+}
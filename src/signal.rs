@@ -0,0 +1,87 @@
+//! Delivery of signals into a poll loop through a self-pipe.
+
+use std::convert::TryFrom;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use nix::fcntl::OFlag;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::{libc, unistd};
+
+/// Write end of the self-pipe that delivers signals to the poll loop.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Signals that end the session. The terminal is restored before exiting.
+pub const TERMINATION_SIGNALS: [Signal; 4] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+];
+
+/// Signals that are passed on to the program running on the PTY: the
+/// termination signals, and SIGUSR1.
+pub const FORWARDED_SIGNALS: [Signal; 5] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+];
+
+/// Signal handler that writes the signal number to the self-pipe.
+/// It leaves errno as it was, for the code it interrupted.
+extern "C" fn write_signal(signo: libc::c_int) {
+    let errno = errno_location();
+    let saved = unsafe { *errno };
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    let _ = unistd::write(fd, &[signo as u8]);
+    unsafe { *errno = saved };
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn errno_location() -> *mut libc::c_int {
+    unsafe { libc::__errno_location() }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn errno_location() -> *mut libc::c_int {
+    unsafe { libc::__error() }
+}
+
+/// Opens a self-pipe and routes `signals` into it.
+/// Returns the non-blocking read end, which yields one byte per signal received.
+pub fn signal_pipe(signals: &[Signal]) -> Result<RawFd, nix::Error> {
+    let (rx, tx) = unistd::pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+    SIGNAL_PIPE.store(tx, Ordering::Relaxed);
+
+    let action = SigAction::new(
+        SigHandler::Handler(write_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for &sig in signals {
+        unsafe { signal::sigaction(sig, &action) }?;
+    }
+
+    Ok(rx)
+}
+
+/// Reads all pending signals from the self-pipe `rx`.
+pub fn read_signals(rx: RawFd) -> Result<Vec<Signal>, nix::Error> {
+    let mut signals = Vec::new();
+    let mut buf = [0u8; 32];
+    loop {
+        match unistd::read(rx, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => signals.extend(
+                buf[..n]
+                    .iter()
+                    .filter_map(|&signo| Signal::try_from(signo as libc::c_int).ok()),
+            ),
+            Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(signals)
+}
@@ -0,0 +1,250 @@
+//! Snapshot testing of what programs show on the screen.
+//!
+//! A test runs a program on a headless terminal, waits for its screen to
+//! settle or to show a pattern, and compares the text on the screen to a
+//! snapshot file checked in next to the tests:
+//!
+//! ```no_run
+//! use ptyme::snapshot::{self, SnapshotOptions};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let cmd = ["ls".to_string(), "--color".to_string()];
+//! snapshot::check("tests/snapshots/ls.txt", &cmd, &SnapshotOptions::default())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Running the tests with `PTYME_BLESS=1` writes the current screens to the
+//! snapshot files instead, to create them or to accept changes.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::headless::HeadlessTerm;
+use crate::regex::Regex;
+use crate::screen::Screen;
+use crate::term::Winsize;
+
+/// Environment variable that makes snapshots be written rather than
+/// compared, when set to anything but empty or `0`.
+pub const BLESS_VAR: &str = "PTYME_BLESS";
+
+/// What to wait for before taking a snapshot.
+#[derive(Debug, Clone)]
+pub enum Wait {
+    /// The screen not changing for this long, or the program exiting.
+    Stable(Duration),
+    /// The text on the screen matching a pattern.
+    Pattern(Regex),
+}
+
+/// How a program is run for a snapshot.
+#[derive(Debug, Clone)]
+pub struct SnapshotOptions {
+    /// Size of the screen, 24 by 80 by default.
+    pub winsize: Winsize,
+    /// What to wait for, the screen being stable for 200ms by default.
+    pub wait: Wait,
+    /// How long to wait at most, 10 seconds by default.
+    pub timeout: Duration,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> SnapshotOptions {
+        SnapshotOptions {
+            winsize: Winsize {
+                ws_row: 24,
+                ws_col: 80,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            },
+            wait: Wait::Stable(Duration::from_millis(200)),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A screen that didn't match its snapshot.
+#[derive(Debug, Clone)]
+pub struct SnapshotError {
+    /// The snapshot file.
+    pub path: PathBuf,
+    /// The contents of the snapshot file, if it exists.
+    pub expected: Option<String>,
+    /// The text on the screen.
+    pub actual: String,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let expected = match self.expected {
+            Some(ref expected) => expected,
+            None => {
+                return write!(
+                    f,
+                    "snapshot {} does not exist, rerun with {}=1 to create it:\n{}",
+                    self.path.display(),
+                    BLESS_VAR,
+                    self.actual
+                )
+            }
+        };
+
+        writeln!(
+            f,
+            "snapshot {} does not match, rerun with {}=1 to update it:",
+            self.path.display(),
+            BLESS_VAR
+        )?;
+        // Screens are grids, so they are compared row by row.
+        let expected: Vec<_> = expected.lines().collect();
+        let actual: Vec<_> = self.actual.lines().collect();
+        for row in 0..expected.len().max(actual.len()) {
+            let (old, new) = (expected.get(row), actual.get(row));
+            if old != new {
+                writeln!(f, "row {}:", row + 1)?;
+                writeln!(f, "-{}", old.unwrap_or(&""))?;
+                writeln!(f, "+{}", new.unwrap_or(&""))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for SnapshotError {}
+
+/// Whether snapshots are to be written, rather than compared.
+fn blessing() -> bool {
+    env::var(BLESS_VAR).is_ok_and(|val| !val.is_empty() && val != "0")
+}
+
+/// Runs `cmd` on a headless terminal and returns its screen once `opts.wait`
+/// is satisfied. The program is hung up afterwards, unless it exited.
+pub fn capture(cmd: &[String], opts: &SnapshotOptions) -> Result<Screen, Box<dyn Error>> {
+    let mut term = HeadlessTerm::spawn(cmd, &opts.winsize)?;
+    match opts.wait {
+        Wait::Stable(quiet) => term.wait_stable(quiet, Some(opts.timeout))?,
+        Wait::Pattern(ref re) => term.wait_for(
+            |screen| re.is_match(screen.contents().as_bytes()),
+            Some(opts.timeout),
+        )?,
+    }
+    let screen = term.screen().clone();
+    term.close()?;
+    Ok(screen)
+}
+
+/// Compares the text on `screen` to the snapshot at `path`, or writes it
+/// there if `PTYME_BLESS` is set. Fails with a `SnapshotError` if they
+/// differ.
+pub fn assert_screen(path: impl AsRef<Path>, screen: &Screen) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let actual = format!("{}\n", screen.contents());
+
+    if blessing() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, actual)?;
+        return Ok(());
+    }
+
+    let expected = match fs::read_to_string(path) {
+        Ok(expected) => Some(expected),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    if expected.as_ref() == Some(&actual) {
+        return Ok(());
+    }
+    Err(SnapshotError {
+        path: path.to_path_buf(),
+        expected,
+        actual,
+    }
+    .into())
+}
+
+/// Runs `cmd` on a headless terminal, and compares its screen to the
+/// snapshot at `path` once `opts.wait` is satisfied. See `assert_screen`.
+pub fn check(
+    path: impl AsRef<Path>,
+    cmd: &[String],
+    opts: &SnapshotOptions,
+) -> Result<(), Box<dyn Error>> {
+    assert_screen(path, &capture(cmd, opts)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(text: &[u8]) -> Screen {
+        let mut screen = Screen::new(3, 10);
+        screen.process(text);
+        screen
+    }
+
+    // A single test, so that tests running alongside it can't see
+    // PTYME_BLESS change under them.
+    #[test]
+    fn golden_files() {
+        let dir = env::temp_dir().join(format!("ptyme-snapshot-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("nested").join("screen.txt");
+        let hello = screen(b"hello\r\nworld");
+        let golden = format!("{}\n", hello.contents());
+
+        // A missing snapshot fails, and isn't created.
+        env::remove_var(BLESS_VAR);
+        let err = assert_screen(&path, &hello).unwrap_err();
+        let err = err.downcast::<SnapshotError>().unwrap();
+        assert_eq!(err.expected, None);
+        assert_eq!(err.actual, golden);
+        assert!(err.to_string().starts_with(&format!(
+            "snapshot {} does not exist, rerun with PTYME_BLESS=1 to create it:\n",
+            path.display()
+        )));
+        assert!(!path.exists());
+
+        // Blessing creates it, along with its directory.
+        env::set_var(BLESS_VAR, "1");
+        assert_screen(&path, &hello).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), golden);
+
+        // With blessing off again, the same screen matches and another
+        // doesn't, and the rows that differ are shown.
+        env::set_var(BLESS_VAR, "0");
+        assert_screen(&path, &hello).unwrap();
+        let goodbye = screen(b"goodbye\r\nworld\r\n!");
+        let err = assert_screen(&path, &goodbye).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "snapshot {} does not match, rerun with PTYME_BLESS=1 to update it:\n\
+                 row 1:\n-hello\n+goodbye\nrow 3:\n-\n+!\n",
+                path.display()
+            )
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), golden);
+
+        // Blessing overwrites it with the new screen.
+        env::set_var(BLESS_VAR, "yes");
+        assert_screen(&path, &goodbye).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", goodbye.contents())
+        );
+        env::set_var(BLESS_VAR, "");
+        assert_screen(&path, &goodbye).unwrap();
+        assert!(assert_screen(&path, &hello).is_err());
+
+        env::remove_var(BLESS_VAR);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
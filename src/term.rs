@@ -0,0 +1,344 @@
+//! Settings of the terminal the current process is attached to.
+
+use std::os::unix::io::RawFd;
+use std::panic;
+use std::str::FromStr;
+use std::sync::{Mutex, Once};
+
+use nix::libc;
+use nix::sys::termios::{
+    self, BaudRate, InputFlags, LocalFlags, OutputFlags, SpecialCharacterIndices, Termios,
+};
+
+pub use nix::pty::Winsize;
+
+mod ioctl {
+    use nix::libc;
+    use nix::pty::Winsize;
+
+    // Gets and sets the window size of a terminal.
+    nix::ioctl_read_bad!(tiocgwinsz, libc::TIOCGWINSZ, Winsize);
+    nix::ioctl_write_ptr_bad!(tiocswinsz, libc::TIOCSWINSZ, Winsize);
+}
+
+/// Configures the given term to be in 'raw' mode.
+pub fn term_set_raw(fd: RawFd, termios: &mut termios::Termios) -> Result<(), nix::Error> {
+    termios::cfmakeraw(termios);
+    termios::tcsetattr(fd, termios::SetArg::TCSANOW, termios)
+}
+
+/// Changes to the settings of a terminal, such as those of a PTY slave for
+/// the program running on it. Settings that are `None` are left alone.
+///
+/// Can be parsed from `stty`-style settings, separated by whitespace:
+/// `[-]echo`, `[-]icanon`, `[-]icrnl`, `[-]onlcr`, a baud rate such as `9600`,
+/// and `intr`, `eof` or `erase` followed by a character, such as `^C`, or
+/// `undef`. For example `-echo -onlcr erase ^H`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TermSettings {
+    /// Whether input is echoed back.
+    pub echo: Option<bool>,
+    /// Whether input is read a line at a time, with line editing.
+    pub canonical: Option<bool>,
+    /// Whether carriage returns in input are turned into newlines.
+    pub icrnl: Option<bool>,
+    /// Whether newlines in output are turned into carriage return, newline.
+    pub onlcr: Option<bool>,
+    /// The input and output speed.
+    pub baud_rate: Option<BaudRate>,
+    /// The character that sends SIGINT.
+    pub intr: Option<u8>,
+    /// The end of file character.
+    pub eof: Option<u8>,
+    /// The character that erases the previous one.
+    pub erase: Option<u8>,
+}
+
+impl TermSettings {
+    /// Applies the settings to `termios`.
+    pub fn apply(&self, termios: &mut Termios) -> Result<(), nix::Error> {
+        if let Some(echo) = self.echo {
+            termios.local_flags.set(LocalFlags::ECHO, echo);
+        }
+        if let Some(canonical) = self.canonical {
+            termios.local_flags.set(LocalFlags::ICANON, canonical);
+        }
+        if let Some(icrnl) = self.icrnl {
+            termios.input_flags.set(InputFlags::ICRNL, icrnl);
+        }
+        if let Some(onlcr) = self.onlcr {
+            termios.output_flags.set(OutputFlags::ONLCR, onlcr);
+        }
+        if let Some(baud_rate) = self.baud_rate {
+            termios::cfsetspeed(termios, baud_rate)?;
+        }
+        let chars = [
+            (SpecialCharacterIndices::VINTR, self.intr),
+            (SpecialCharacterIndices::VEOF, self.eof),
+            (SpecialCharacterIndices::VERASE, self.erase),
+        ];
+        for &(index, c) in chars.iter() {
+            if let Some(c) = c {
+                termios.control_chars[index as usize] = c;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the settings to the terminal `fd`.
+    pub fn apply_to(&self, fd: RawFd) -> Result<(), nix::Error> {
+        let mut termios = termios::tcgetattr(fd)?;
+        self.apply(&mut termios)?;
+        termios::tcsetattr(fd, termios::SetArg::TCSANOW, &termios)
+    }
+}
+
+impl FromStr for TermSettings {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TermSettings, String> {
+        let mut settings = TermSettings::default();
+        let mut words = s.split_whitespace();
+
+        while let Some(word) = words.next() {
+            let (name, on) = match word.strip_prefix('-') {
+                Some(name) => (name, false),
+                None => (word, true),
+            };
+            match name {
+                "echo" => settings.echo = Some(on),
+                "icanon" => settings.canonical = Some(on),
+                "icrnl" => settings.icrnl = Some(on),
+                "onlcr" => settings.onlcr = Some(on),
+                "intr" | "eof" | "erase" if on => {
+                    let value = words
+                        .next()
+                        .ok_or_else(|| format!("{} requires a character", name))?;
+                    let c = match value {
+                        // Disables the special character.
+                        "undef" => 0,
+                        value => parse_char(value)
+                            .ok_or_else(|| format!("invalid {} character: {:?}", name, value))?,
+                    };
+                    match name {
+                        "intr" => settings.intr = Some(c),
+                        "eof" => settings.eof = Some(c),
+                        _ => settings.erase = Some(c),
+                    }
+                }
+                rate if on && rate.bytes().all(|b| b.is_ascii_digit()) => {
+                    let baud_rate = rate.parse().ok().and_then(baud_rate);
+                    settings.baud_rate =
+                        Some(baud_rate.ok_or_else(|| format!("unsupported baud rate: {}", rate))?);
+                }
+                _ => return Err(format!("unknown terminal setting: {}", word)),
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Returns the `BaudRate` for a speed in bits per second.
+fn baud_rate(rate: u32) -> Option<BaudRate> {
+    Some(match rate {
+        0 => BaudRate::B0,
+        50 => BaudRate::B50,
+        75 => BaudRate::B75,
+        110 => BaudRate::B110,
+        134 => BaudRate::B134,
+        150 => BaudRate::B150,
+        200 => BaudRate::B200,
+        300 => BaudRate::B300,
+        600 => BaudRate::B600,
+        1200 => BaudRate::B1200,
+        1800 => BaudRate::B1800,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115_200 => BaudRate::B115200,
+        230_400 => BaudRate::B230400,
+        _ => return None,
+    })
+}
+
+/// Parses a character given as itself, or a control character in caret
+/// notation, such as `^C` or `^?`.
+pub fn parse_char(s: &str) -> Option<u8> {
+    match s.as_bytes() {
+        [b'^', c] if (b'?'..=b'_').contains(&c.to_ascii_uppercase()) => {
+            Some(c.to_ascii_uppercase() ^ 0x40)
+        }
+        [c] if c.is_ascii() => Some(*c),
+        _ => None,
+    }
+}
+
+/// The terminals in 'raw' mode, and the settings to restore them to if the
+/// process panics, in the order they were put in 'raw' mode.
+static RAW_TERMS: Mutex<Vec<(RawFd, libc::termios)>> = Mutex::new(Vec::new());
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Installs the panic hook that restores the terminals in 'raw' mode, the
+/// first time it is called.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // The panic may have happened while the list was locked.
+            if let Ok(terms) = RAW_TERMS.try_lock() {
+                for (fd, saved) in terms.iter().rev() {
+                    let _ = termios::tcsetattr(*fd, termios::SetArg::TCSANOW, &(*saved).into());
+                }
+            }
+            default_hook(info);
+        }));
+    });
+}
+
+/// Keeps a terminal in 'raw' mode for as long as it is alive.
+/// The original settings are restored when it is dropped, and also
+/// by the panic hook, before the panic message is printed.
+pub struct RawTerm {
+    fd: RawFd,
+    saved: termios::Termios,
+}
+
+impl RawTerm {
+    /// Saves the settings of the terminal `fd` and puts it in 'raw' mode.
+    pub fn new(fd: RawFd) -> Result<RawTerm, nix::Error> {
+        let saved = termios::tcgetattr(fd)?;
+
+        install_panic_hook();
+        term_set_raw(fd, &mut saved.clone())?;
+        if let Ok(mut terms) = RAW_TERMS.lock() {
+            terms.push((fd, saved.clone().into()));
+        }
+
+        Ok(RawTerm { fd, saved })
+    }
+
+    /// The settings the terminal had before it was put in 'raw' mode.
+    pub fn saved(&self) -> &termios::Termios {
+        &self.saved
+    }
+}
+
+impl Drop for RawTerm {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(self.fd, termios::SetArg::TCSANOW, &self.saved);
+        // The panic hook must leave the terminal alone from now on, the fd
+        // may be closed or reused.
+        if let Ok(mut terms) = RAW_TERMS.lock() {
+            if let Some(pos) = terms.iter().rposition(|(fd, _)| *fd == self.fd) {
+                terms.remove(pos);
+            }
+        }
+    }
+}
+
+/// Returns the window size of the terminal `fd`.
+pub fn get_winsize(fd: RawFd) -> Result<Winsize, nix::Error> {
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    unsafe { ioctl::tiocgwinsz(fd, &mut winsize) }?;
+    Ok(winsize)
+}
+
+/// Sets the window size of the terminal `fd`.
+pub fn set_winsize(fd: RawFd, winsize: &Winsize) -> Result<(), nix::Error> {
+    unsafe { ioctl::tiocswinsz(fd, winsize) }?;
+    Ok(())
+}
+
+/// Copies the settings of the terminal `from` onto the terminal `to`.
+pub fn copy_termios(from: RawFd, to: RawFd) -> Result<(), nix::Error> {
+    termios::tcsetattr(to, termios::SetArg::TCSANOW, &termios::tcgetattr(from)?)
+}
+
+/// Copies the window size of the terminal `from` onto the terminal `to`.
+pub fn copy_winsize(from: RawFd, to: RawFd) -> Result<(), nix::Error> {
+    set_winsize(to, &get_winsize(from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings() {
+        let settings: TermSettings = "-echo -onlcr erase ^H".parse().unwrap();
+        assert_eq!(
+            settings,
+            TermSettings {
+                echo: Some(false),
+                onlcr: Some(false),
+                erase: Some(0x08),
+                ..TermSettings::default()
+            }
+        );
+
+        let settings: TermSettings = " icanon\ticrnl  9600 intr undef eof x ".parse().unwrap();
+        assert_eq!(
+            settings,
+            TermSettings {
+                canonical: Some(true),
+                icrnl: Some(true),
+                baud_rate: Some(BaudRate::B9600),
+                intr: Some(0),
+                eof: Some(b'x'),
+                ..TermSettings::default()
+            }
+        );
+
+        assert_eq!("".parse(), Ok(TermSettings::default()));
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let cases = [
+            ("intr", "intr requires a character"),
+            ("echo eof", "eof requires a character"),
+            ("erase ^1", "invalid erase character: \"^1\""),
+            ("intr ab", "invalid intr character: \"ab\""),
+            ("-intr ^C", "unknown terminal setting: -intr"),
+            ("9601", "unsupported baud rate: 9601"),
+            ("99999999999", "unsupported baud rate: 99999999999"),
+            ("-9600", "unknown terminal setting: -9600"),
+            ("echo raw", "unknown terminal setting: raw"),
+        ];
+        for (input, err) in cases.iter() {
+            assert_eq!(
+                input.parse::<TermSettings>(),
+                Err(err.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn caret_notation() {
+        let cases = [
+            ("^C", Some(0x03)),
+            ("^c", Some(0x03)),
+            ("^H", Some(0x08)),
+            ("^?", Some(0x7f)),
+            ("^@", Some(0x00)),
+            ("^[", Some(0x1b)),
+            ("^_", Some(0x1f)),
+            ("x", Some(b'x')),
+            ("^", Some(b'^')),
+            ("^1", None),
+            ("^CC", None),
+            ("é", None),
+            ("", None),
+        ];
+        for &(input, c) in cases.iter() {
+            assert_eq!(parse_char(input), c, "{}", input);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Logging of session output in the typescript format of `script(1)`, with
+//! optional timing data for `scriptreplay(1)`.
+//!
+//! The typescript is the output of the session, verbatim, between a
+//! "Script started on ..." header line and a "Script done on ..." footer
+//! line, as written by util-linux `script`. Each line of the timing file is
+//! `<delay> <bytes>`: the seconds since the previous chunk of output, and
+//! the number of bytes in this one.
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::ptr;
+use std::time::Instant;
+
+use nix::libc;
+
+use crate::filter::{keep_error, Filter};
+use crate::pty::ExitStatus;
+use crate::term::Winsize;
+
+extern "C" {
+    // Not bound by the libc crate.
+    fn strftime(
+        s: *mut libc::c_char,
+        max: libc::size_t,
+        format: *const libc::c_char,
+        tm: *const libc::tm,
+    ) -> libc::size_t;
+}
+
+/// Writes the output of a session to a typescript, and optionally its timing.
+pub struct Typescript<W: Write = BufWriter<File>> {
+    file: W,
+    timing: Option<W>,
+    // When the last chunk of output was written.
+    last: Instant,
+    // The first error writing the files as a filter, for `take_error`.
+    error: Option<io::Error>,
+}
+
+impl Typescript {
+    /// Creates the typescript at `path`, and the timing file at `timing` if
+    /// given, and writes the header for a terminal of size `winsize`
+    /// running `cmd`.
+    pub fn create(
+        path: impl AsRef<Path>,
+        timing: Option<&Path>,
+        winsize: &Winsize,
+        cmd: &[String],
+    ) -> Result<Typescript, Box<dyn Error>> {
+        let file = BufWriter::new(File::create(path)?);
+        let timing = match timing {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+        Ok(Typescript::new(file, timing, winsize, cmd)?)
+    }
+}
+
+impl<W: Write> Typescript<W> {
+    /// Writes the typescript to `file` and the timing to `timing` if given,
+    /// starting with the header for a terminal of size `winsize` running
+    /// `cmd`.
+    pub fn new(
+        mut file: W,
+        timing: Option<W>,
+        winsize: &Winsize,
+        cmd: &[String],
+    ) -> io::Result<Typescript<W>> {
+        write!(file, "Script started on {} [", local_time())?;
+        if !cmd.is_empty() {
+            write!(file, "COMMAND=\"{}\" ", cmd.join(" "))?;
+        }
+        if let Ok(term) = env::var("TERM") {
+            write!(file, "TERM=\"{}\" ", term)?;
+        }
+        writeln!(
+            file,
+            "COLUMNS=\"{}\" LINES=\"{}\"]",
+            winsize.ws_col, winsize.ws_row
+        )?;
+        file.flush()?;
+
+        Ok(Typescript {
+            file,
+            timing,
+            last: Instant::now(),
+            error: None,
+        })
+    }
+
+    /// Logs `data` written by the program to the terminal.
+    pub fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.file.flush()?;
+
+        if let Some(timing) = self.timing.as_mut() {
+            let now = Instant::now();
+            writeln!(
+                timing,
+                "{:.6} {}",
+                now.duration_since(self.last).as_secs_f64(),
+                data.len()
+            )?;
+            timing.flush()?;
+            self.last = now;
+        }
+        Ok(())
+    }
+
+    /// Writes the footer, for a program that exited with `status`.
+    pub fn finish(mut self, status: i32) -> io::Result<()> {
+        self.write_footer(status)
+    }
+
+    fn write_footer(&mut self, status: i32) -> io::Result<()> {
+        write!(
+            self.file,
+            "\nScript done on {} [COMMAND_EXIT_CODE=\"{}\"]\n",
+            local_time(),
+            status
+        )?;
+        self.file.flush()
+    }
+}
+
+/// Logs the output as it passes through, without changing anything, and
+/// writes the footer once the session ends.
+impl<W: Write> Filter for Typescript<W> {
+    fn on_output(&mut self, data: &[u8]) -> Vec<u8> {
+        let res = self.output(data);
+        keep_error(&mut self.error, res);
+        data.to_vec()
+    }
+
+    fn on_exit(&mut self, status: &ExitStatus) -> io::Result<()> {
+        self.write_footer(status.code)
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+}
+
+/// Returns the current local time as `script` prints it, such as
+/// `2020-05-04 11:26:39+02:00`.
+pub(crate) fn local_time() -> String {
+    let mut buf = [0u8; 64];
+    let len = unsafe {
+        let now = libc::time(ptr::null_mut());
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        let format = b"%Y-%m-%d %H:%M:%S%z\0";
+        strftime(
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            format.as_ptr() as *const libc::c_char,
+            &tm,
+        )
+    };
+
+    // strftime has no way to put a colon in the UTC offset.
+    let mut time = String::from_utf8_lossy(&buf[..len]).into_owned();
+    if time.len() > 2 {
+        time.insert(time.len() - 2, ':');
+    }
+    time
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `line` is `prefix`, a time as `local_time` prints it,
+    /// then `suffix`.
+    fn assert_timestamped(line: &str, prefix: &str, suffix: &str) {
+        let time = line
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix))
+            .unwrap_or_else(|| panic!("{:?}", line));
+        let shape = time
+            .bytes()
+            .map(|b| if b.is_ascii_digit() { b'0' } else { b })
+            .collect::<Vec<u8>>();
+        assert!(
+            shape == b"0000-00-00 00:00:00+00:00" || shape == b"0000-00-00 00:00:00-00:00",
+            "{:?}",
+            line
+        );
+    }
+
+    #[test]
+    fn script_format() {
+        let (mut file, mut timing) = (Vec::new(), Vec::new());
+        let winsize = Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let cmd = ["echo".to_string(), "hi".to_string()];
+        let mut script = Typescript::new(&mut file, Some(&mut timing), &winsize, &cmd).unwrap();
+        script.output(b"hi\r\n").unwrap();
+        script.output(b"").unwrap();
+        script.output(b"\x1b[0m").unwrap();
+        script.finish(3).unwrap();
+
+        let file = String::from_utf8(file).unwrap();
+        let lines: Vec<&str> = file.split('\n').collect();
+        let term = match env::var("TERM") {
+            Ok(term) => format!("TERM=\"{}\" ", term),
+            Err(_) => String::new(),
+        };
+        let header_end = format!(" [COMMAND=\"echo hi\" {}COLUMNS=\"80\" LINES=\"24\"]", term);
+        // The footer goes on a line of its own.
+        assert_eq!(lines.len(), 5, "{:?}", file);
+        assert_timestamped(lines[0], "Script started on ", &header_end);
+        assert_eq!(lines[1], "hi\r");
+        assert_eq!(lines[2], "\x1b[0m");
+        assert_timestamped(lines[3], "Script done on ", " [COMMAND_EXIT_CODE=\"3\"]");
+        assert_eq!(lines[4], "");
+
+        // Each timing line is the delay in seconds, then the byte count.
+        let timing = String::from_utf8(timing).unwrap();
+        let lines: Vec<(&str, &str)> = timing
+            .lines()
+            .map(|line| line.split_once(' ').unwrap())
+            .collect();
+        let counts: Vec<&str> = lines.iter().map(|&(_, count)| count).collect();
+        assert_eq!(counts, ["4", "0", "4"]);
+        for (delay, _) in lines {
+            let (secs, micros) = delay.split_once('.').unwrap();
+            assert!(secs.parse::<u64>().is_ok(), "{}", delay);
+            assert!(
+                micros.len() == 6 && micros.bytes().all(|b| b.is_ascii_digit()),
+                "{}",
+                delay
+            );
+        }
+        assert!(timing.ends_with('\n'));
+    }
+
+    #[test]
+    fn no_command_or_timing() {
+        let mut file = Vec::new();
+        let winsize = Winsize {
+            ws_row: 50,
+            ws_col: 132,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        Typescript::new(&mut file, None, &winsize, &[])
+            .unwrap()
+            .finish(0)
+            .unwrap();
+
+        let file = String::from_utf8(file).unwrap();
+        let lines: Vec<&str> = file.split('\n').collect();
+        let term = match env::var("TERM") {
+            Ok(term) => format!("TERM=\"{}\" ", term),
+            Err(_) => String::new(),
+        };
+        let header_end = format!(" [{}COLUMNS=\"132\" LINES=\"50\"]", term);
+        assert_eq!(lines.len(), 4, "{:?}", file);
+        assert_timestamped(lines[0], "Script started on ", &header_end);
+        assert_eq!(lines[1], "");
+        assert_timestamped(lines[2], "Script done on ", " [COMMAND_EXIT_CODE=\"0\"]");
+        assert_eq!(lines[3], "");
+    }
+}
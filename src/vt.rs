@@ -0,0 +1,395 @@
+//! A parser for the escape sequences of VT100 compatible terminals, such as
+//! xterm, following the state machine of the DEC ANSI parser.
+//!
+//! The parser splits a stream of output into printable characters, control
+//! characters, and escape, CSI and OSC sequences, and hands them to a
+//! `Perform` implementation, which gives them meaning. Output is decoded as
+//! UTF-8, with invalid sequences printed as U+FFFD. The stream may be split
+//! anywhere, the parser picks up where the last chunk left off.
+
+/// Maximum number of CSI parameters kept, any further ones are dropped.
+const MAX_PARAMS: usize = 32;
+
+/// Maximum length of the OSC strings kept, the rest is dropped.
+const MAX_OSC: usize = 4096;
+
+/// Acts on what the parser finds in the stream.
+pub trait Perform {
+    /// Prints `c` at the cursor.
+    fn print(&mut self, c: char);
+
+    /// Executes the C0 control character `byte`, such as `\r` or `\n`.
+    fn execute(&mut self, byte: u8);
+
+    /// Dispatches the control sequence `ESC [ <intermediates> <params> <action>`.
+    /// Private markers such as `?` are included in the `intermediates`,
+    /// and parameters that were left out are 0.
+    fn csi_dispatch(&mut self, _params: &[u16], _intermediates: &[u8], _action: u8) {}
+
+    /// Dispatches the escape sequence `ESC <intermediates> <byte>`.
+    fn esc_dispatch(&mut self, _intermediates: &[u8], _byte: u8) {}
+
+    /// Dispatches the operating system command `ESC ] <data> ST`, such as a
+    /// request to set the window title.
+    fn osc_dispatch(&mut self, _data: &[u8]) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiParam,
+    CsiIgnore,
+    Osc,
+    OscEscape,
+    // Device control strings and the like, which are ignored.
+    String,
+    StringEscape,
+}
+
+/// A parser for a stream of terminal output.
+#[derive(Debug, Clone)]
+pub struct Parser {
+    state: State,
+    params: Vec<u16>,
+    // The parameter being parsed, if any of its digits have been seen.
+    param: Option<u16>,
+    intermediates: Vec<u8>,
+    osc: Vec<u8>,
+    // Bytes of an incomplete UTF-8 sequence, and how many it needs in total.
+    utf8: Vec<u8>,
+    utf8_len: usize,
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    /// Returns a parser in its initial state.
+    pub fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: Vec::new(),
+            param: None,
+            intermediates: Vec::new(),
+            osc: Vec::new(),
+            utf8: Vec::new(),
+            utf8_len: 0,
+        }
+    }
+
+    /// Parses `data`, handing everything found in it to `performer`.
+    pub fn advance(&mut self, performer: &mut impl Perform, data: &[u8]) {
+        for &byte in data {
+            self.byte(performer, byte);
+        }
+    }
+
+    fn byte(&mut self, performer: &mut impl Perform, byte: u8) {
+        if self.state == State::Ground && (byte >= 0x80 || !self.utf8.is_empty()) {
+            self.utf8_byte(performer, byte);
+            return;
+        }
+
+        // Cancelling a sequence, or starting a new one, works in any state but
+        // the strings, which may be ended by ESC \.
+        match (byte, self.state) {
+            (0x18, _) | (0x1a, _) => {
+                performer.execute(byte);
+                self.state = State::Ground;
+                return;
+            }
+            (0x1b, State::Osc) => {
+                self.state = State::OscEscape;
+                return;
+            }
+            (0x1b, State::String) => {
+                self.state = State::StringEscape;
+                return;
+            }
+            (0x1b, State::OscEscape) | (0x1b, State::StringEscape) => {}
+            (0x1b, _) => {
+                self.enter_escape();
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x7f => {}
+                _ => performer.print(byte as char),
+            },
+            State::Escape => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => {
+                    self.intermediates.push(byte);
+                    self.state = State::EscapeIntermediate;
+                }
+                b'[' => {
+                    self.params.clear();
+                    self.param = None;
+                    self.state = State::CsiParam;
+                }
+                b']' => {
+                    self.osc.clear();
+                    self.state = State::Osc;
+                }
+                b'P' | b'X' | b'^' | b'_' => self.state = State::String,
+                0x30..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::EscapeIntermediate => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x20..=0x2f => self.intermediates.push(byte),
+                0x30..=0x7e => {
+                    performer.esc_dispatch(&self.intermediates, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::CsiParam => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                b'0'..=b'9' => {
+                    let digit = u16::from(byte - b'0');
+                    let param = self.param.unwrap_or(0);
+                    self.param = Some(param.saturating_mul(10).saturating_add(digit));
+                }
+                // Sub-parameters, as in `38:2:r:g:b`, are taken as parameters.
+                b';' | b':' => self.push_param(),
+                // Private markers only come first.
+                b'<'..=b'?' if self.params.is_empty() && self.param.is_none() => {
+                    self.intermediates.push(byte)
+                }
+                b'<'..=b'?' => self.state = State::CsiIgnore,
+                0x20..=0x2f => self.intermediates.push(byte),
+                0x40..=0x7e => {
+                    if self.param.is_some() || !self.params.is_empty() {
+                        self.push_param();
+                    }
+                    performer.csi_dispatch(&self.params, &self.intermediates, byte);
+                    self.state = State::Ground;
+                }
+                _ => {}
+            },
+            State::CsiIgnore => match byte {
+                0x00..=0x1f => performer.execute(byte),
+                0x40..=0x7e => self.state = State::Ground,
+                _ => {}
+            },
+            State::Osc => match byte {
+                // BEL ends the string as well as ST does.
+                0x07 => {
+                    performer.osc_dispatch(&self.osc);
+                    self.state = State::Ground;
+                }
+                0x00..=0x1f => {}
+                _ if self.osc.len() < MAX_OSC => self.osc.push(byte),
+                _ => {}
+            },
+            State::OscEscape => {
+                performer.osc_dispatch(&self.osc);
+                self.reenter_escape(performer, byte);
+            }
+            State::String => {}
+            State::StringEscape => self.reenter_escape(performer, byte),
+        }
+    }
+
+    /// Handles the byte following an ESC that ended a string. Anything but
+    /// `\` starts a new escape sequence.
+    fn reenter_escape(&mut self, performer: &mut impl Perform, byte: u8) {
+        if byte == b'\\' {
+            self.state = State::Ground;
+        } else {
+            self.enter_escape();
+            self.byte(performer, byte);
+        }
+    }
+
+    fn enter_escape(&mut self) {
+        self.intermediates.clear();
+        self.state = State::Escape;
+    }
+
+    fn push_param(&mut self) {
+        if self.params.len() < MAX_PARAMS {
+            self.params.push(self.param.unwrap_or(0));
+        }
+        self.param = None;
+    }
+
+    /// Decodes the UTF-8 encoded characters of the ground state.
+    fn utf8_byte(&mut self, performer: &mut impl Perform, byte: u8) {
+        if self.utf8.is_empty() {
+            self.utf8_len = match byte {
+                0xc2..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf4 => 4,
+                _ => {
+                    performer.print(char::REPLACEMENT_CHARACTER);
+                    return;
+                }
+            };
+            self.utf8.push(byte);
+            return;
+        }
+
+        if byte & 0xc0 != 0x80 {
+            // The sequence was cut short, the byte starts something new.
+            self.utf8.clear();
+            performer.print(char::REPLACEMENT_CHARACTER);
+            self.byte(performer, byte);
+            return;
+        }
+
+        self.utf8.push(byte);
+        if self.utf8.len() == self.utf8_len {
+            let c = std::str::from_utf8(&self.utf8)
+                .ok()
+                .and_then(|s| s.chars().next())
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.utf8.clear();
+            performer.print(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what the parser found, with runs of printed characters
+    /// collected into one quoted string.
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Perform for Log {
+        fn print(&mut self, c: char) {
+            match self.0.last_mut() {
+                Some(last) if last.starts_with('\'') => {
+                    last.pop();
+                    last.push(c);
+                    last.push('\'');
+                }
+                _ => self.0.push(format!("'{}'", c)),
+            }
+        }
+
+        fn execute(&mut self, byte: u8) {
+            self.0.push(format!("exec {:02x}", byte));
+        }
+
+        fn csi_dispatch(&mut self, params: &[u16], intermediates: &[u8], action: u8) {
+            self.0.push(format!(
+                "csi {:?} {}{}",
+                params,
+                String::from_utf8_lossy(intermediates),
+                action as char
+            ));
+        }
+
+        fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+            self.0.push(format!(
+                "esc {}{}",
+                String::from_utf8_lossy(intermediates),
+                byte as char
+            ));
+        }
+
+        fn osc_dispatch(&mut self, data: &[u8]) {
+            self.0
+                .push(format!("osc {}", String::from_utf8_lossy(data)));
+        }
+    }
+
+    fn parse_chunks(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = Parser::new();
+        let mut log = Log::default();
+        for chunk in chunks {
+            parser.advance(&mut log, chunk);
+        }
+        log.0
+    }
+
+    const CASES: &[(&[u8], &[&str])] = &[
+        // Text and control characters.
+        (b"ab\r\n", &["'ab'", "exec 0d", "exec 0a"]),
+        (b"a\x7fb\x07", &["'ab'", "exec 07"]),
+        // CSI sequences.
+        (b"\x1b[m", &["csi [] m"]),
+        (b"\x1b[1;31m", &["csi [1, 31] m"]),
+        (b"\x1b[;5H", &["csi [0, 5] H"]),
+        (b"\x1b[2;H", &["csi [2, 0] H"]),
+        (b"\x1b[?25l", &["csi [25] ?l"]),
+        (b"\x1b[>0c", &["csi [0] >c"]),
+        (b"\x1b[2 q", &["csi [2]  q"]),
+        (b"\x1b[38:2:1:2:3m", &["csi [38, 2, 1, 2, 3] m"]),
+        (b"\x1b[99999A", &["csi [65535] A"]),
+        (b"\x1b[1\n2A", &["exec 0a", "csi [12] A"]),
+        // A private marker after parameters makes the sequence invalid.
+        (b"\x1b[1?2h x", &["' x'"]),
+        // ESC starts over, CAN and SUB cancel.
+        (b"\x1b[12\x1b[3B", &["csi [3] B"]),
+        (b"\x1b[12\x18x", &["exec 18", "'x'"]),
+        (b"\x1b]0;t\x1ax", &["exec 1a", "'x'"]),
+        // Escape sequences.
+        (b"\x1b7\x1b8", &["esc 7", "esc 8"]),
+        (b"\x1b(B\x1b#8", &["esc (B", "esc #8"]),
+        (b"\x1b\n", &["exec 0a"]),
+        // OSC strings, ended by BEL or ST.
+        (b"\x1b]0;title\x07x", &["osc 0;title", "'x'"]),
+        (b"\x1b]2;t\x1b\\", &["osc 2;t"]),
+        (b"\x1b]2;a\nb\x07", &["osc 2;ab"]),
+        (b"\x1b]2;t\x1b[1m", &["osc 2;t", "csi [1] m"]),
+        // DCS, SOS, PM and APC strings are dropped whole.
+        (b"\x1bPq#0;2;0;0;0\x1b\\ok", &["'ok'"]),
+        (b"\x1bP1$r\x1b[2J", &["csi [2] J"]),
+        (b"\x1b_a\x07b\x1b\\c", &["'c'"]),
+        (b"\x1bXa\x1b\x1b7", &["esc 7"]),
+        // UTF-8, and what is made of invalid sequences.
+        ("é€😀".as_bytes(), &["'é€😀'"]),
+        (b"a\xffb", &["'a\u{fffd}b'"]),
+        (b"\xe2\x82x", &["'\u{fffd}x'"]),
+        (b"\xc3\x1b[m", &["'\u{fffd}'", "csi [] m"]),
+        (b"\xed\xa0\x80", &["'\u{fffd}'"]),
+    ];
+
+    #[test]
+    fn sequences() {
+        for (input, expected) in CASES {
+            assert_eq!(parse_chunks(&[input]), *expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn sequences_split_across_buffers() {
+        for (input, expected) in CASES {
+            for i in 0..=input.len() {
+                let (a, b) = input.split_at(i);
+                assert_eq!(parse_chunks(&[a, b]), *expected, "{:?} at {}", input, i);
+            }
+            let bytes: Vec<&[u8]> = input.chunks(1).collect();
+            assert_eq!(parse_chunks(&bytes), *expected, "{:?} bytewise", input);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let params = "1;".repeat(40) + "m";
+        let csi = parse_chunks(&[b"\x1b[", params.as_bytes()]);
+        assert_eq!(csi, [format!("csi {:?} m", [1; MAX_PARAMS])]);
+
+        let title = "x".repeat(MAX_OSC + 10);
+        let osc = parse_chunks(&[b"\x1b]", title.as_bytes(), b"\x07"]);
+        assert_eq!(osc, [format!("osc {}", &title[..MAX_OSC])]);
+    }
+}